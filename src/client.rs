@@ -0,0 +1,270 @@
+//! Client half of the socket protocol.
+//!
+//! Used when a server is already running so the request can be passed along
+//! to it instead of opening windows in this process.
+
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use crate::logic::msg::{Launch, Msg, RawRequest, Response, ResponseBody, WindowProps};
+use crate::prelude::*;
+
+#[derive(Debug)]
+pub enum ClientError {
+    Write,
+    Read,
+    Hangup,
+    Rejected(String),
+    Spawn,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Write => f.write_str("failed to send request to server"),
+            Self::Read => f.write_str("failed to read response from server"),
+            Self::Hangup => f.write_str("server closed the connection"),
+            Self::Rejected(e) => write!(f, "server rejected request: {e}"),
+            Self::Spawn => f.write_str("failed to start a server"),
+        }
+    }
+}
+impl Context for ClientError {}
+
+/// A connection to a running server.
+pub struct Client {
+    writer: UnixStream,
+    reader: BufReader<UnixStream>,
+    next_id: u64,
+}
+
+impl Client {
+    /// Connects to the server listening at `path`, failing if there is none.
+    pub fn connect(path: impl AsRef<Path>) -> io::Result<Self> {
+        let writer = UnixStream::connect(path)?;
+        let reader = BufReader::new(writer.try_clone()?);
+        Ok(Self {
+            writer,
+            reader,
+            next_id: 0,
+        })
+    }
+
+    /// Starts a server detached from this terminal and connects to it once it
+    /// accepts connections.
+    ///
+    /// The server's output goes to a log file next to the socket and it is
+    /// told to listen on `path` explicitly so it does not depend on our
+    /// environment.
+    pub fn spawn(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        /// How long to wait for a freshly started server to start listening.
+        const TIMEOUT: Duration = Duration::from_secs(5);
+
+        let path = path.as_ref();
+        let log = path.with_extension("log");
+
+        let out = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&log)
+            .attach_printable_lazy(|| format!("could not open log file {log:?}"))
+            .change_context(ClientError::Spawn)?;
+        let err = out.try_clone().change_context(ClientError::Spawn)?;
+
+        let exe = std::env::current_exe()
+            .attach_printable("could not find own executable")
+            .change_context(ClientError::Spawn)?;
+
+        let mut cmd = Command::new(exe);
+        // other clients may be about to use it so it should outlive its windows
+        cmd.arg("--server")
+            .arg("--persist")
+            .arg("--socket")
+            .arg(path)
+            .current_dir("/")
+            .stdin(Stdio::null())
+            .stdout(out)
+            .stderr(err);
+
+        // # Safety
+        // setsid is async-signal-safe so it is fine to call between fork and exec
+        unsafe {
+            cmd.pre_exec(|| {
+                // leave our session so the server outlives this terminal
+                if libc::setsid() == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let mut child = cmd
+            .spawn()
+            .attach_printable("could not start server process")
+            .change_context(ClientError::Spawn)?;
+
+        log::info!("started server (pid {}), logging to {log:?}", child.id());
+
+        // the server can exit early if another client won the race to start
+        // one, so keep trying to connect until the timeout either way
+        let mut exited = None;
+        let start = Instant::now();
+        loop {
+            match Self::connect(path) {
+                Ok(client) => return Ok(client),
+                Err(e) if start.elapsed() > TIMEOUT => {
+                    let mut e = Report::new(e)
+                        .attach_printable(format!("server did not start listening, see {log:?}"));
+                    if let Some(status) = exited {
+                        e = e.attach_printable(format!("server exited with {status}"));
+                    }
+                    return Err(e).change_context(ClientError::Spawn);
+                }
+                Err(_) => {}
+            }
+
+            if exited.is_none() {
+                exited = child.try_wait().ok().flatten();
+            }
+
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    }
+
+    /// Sends a request and blocks until the server answers it.
+    pub fn request(&mut self, msg: Msg) -> Result<json::Value, ClientError> {
+        let request_id = self.next_id;
+        self.next_id += 1;
+
+        let raw = RawRequest {
+            request_id: Some(request_id),
+            msg,
+        };
+        let mut line = json::to_string(&raw).change_context(ClientError::Write)?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .change_context(ClientError::Write)?;
+
+        loop {
+            let resp = self.recv()?.ok_or(Report::new(ClientError::Hangup))?;
+            if resp.request_id != Some(request_id) {
+                continue;
+            }
+
+            return match resp.body {
+                ResponseBody::Ok(value) => Ok(value),
+                ResponseBody::Error(e) => Err(Report::new(ClientError::Rejected(e))),
+                ResponseBody::Event(_) => continue,
+            };
+        }
+    }
+
+    /// Reads the next line sent by the server. Returns `None` once it hangs up.
+    pub fn recv(&mut self) -> Result<Option<Response>, ClientError> {
+        let mut line = String::new();
+        let n = self
+            .reader
+            .read_line(&mut line)
+            .change_context(ClientError::Read)?;
+        if n == 0 {
+            return Ok(None);
+        }
+
+        json::from_str(&line)
+            .attach_printable_lazy(|| format!("malformed response: {line:?}"))
+            .change_context(ClientError::Read)
+            .map(Some)
+    }
+
+    /// Opens the windows asked for on the command line and shows the files in
+    /// them, either as the playlist of one window or each in its own. An image
+    /// from stdin gets a window of its own.
+    ///
+    /// Unless `detached` the windows close when this client disconnects.
+    /// Returns the ids of the windows in the order they were opened.
+    pub fn open(&mut self, launch: &Launch, detached: bool) -> Result<Vec<u64>, ClientError> {
+        let mut opened = Vec::new();
+
+        if let Some(data) = &launch.stdin {
+            let props = Launch::props_for(&launch.props, Path::new("stdin"));
+            let id = self.open_window(detached, props)?;
+            opened.push(id);
+            self.request(Msg::ShowImageData {
+                data: data.clone(),
+                id: id.into(),
+                placement: None,
+            })?;
+        }
+
+        // the server does not share our working directory
+        let paths: Vec<PathBuf> = launch
+            .files
+            .iter()
+            .filter_map(|file| match file.canonicalize() {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    log::warn!("skipping {file:?}: {e}");
+                    None
+                }
+            })
+            .collect();
+
+        if paths.is_empty() {
+            return Ok(opened);
+        }
+
+        if !launch.separate {
+            let id = self.open_window(detached, launch.props.clone())?;
+            opened.push(id);
+            self.request(Msg::SetPlaylist {
+                id: id.into(),
+                paths,
+                index: 0,
+            })?;
+            return Ok(opened);
+        }
+
+        for path in paths {
+            let props = Launch::props_for(&launch.props, &path);
+            let id = self.open_window(detached, props)?;
+            opened.push(id);
+            self.request(Msg::ShowImage {
+                path,
+                id: id.into(),
+                placement: None,
+            })?;
+        }
+        Ok(opened)
+    }
+
+    /// Opens an empty window and returns its id.
+    pub fn open_window(&mut self, detached: bool, props: WindowProps) -> Result<u64, ClientError> {
+        let window = self.request(Msg::OpenWindow {
+            detached,
+            name: None,
+            props,
+        })?;
+        window["id"]
+            .as_u64()
+            .ok_or(Report::new(ClientError::Read))
+            .attach_printable_lazy(|| format!("expected window id, got {window}"))
+    }
+
+    /// Subscribes to every event and prints everything the server sends until
+    /// it hangs up.
+    pub fn attach(&mut self) -> Result<(), ClientError> {
+        self.request(Msg::Subscribe { events: Vec::new() })?;
+
+        while let Some(resp) = self.recv()? {
+            let line = json::to_string(&resp).change_context(ClientError::Read)?;
+            println!("{line}");
+        }
+        Ok(())
+    }
+}
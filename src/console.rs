@@ -0,0 +1,637 @@
+//! An interactive prompt for an attached client.
+//!
+//! Every line typed is parsed into a request for the server, so windows can be
+//! stepped through, zoomed and opened without leaving the terminal. Commands
+//! act on the current window, which is the last one opened unless another is
+//! picked with `window <id>`. Lines are kept in a history for the session and
+//! paths complete with tab.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::{cursor, queue, style, terminal};
+
+use crate::client::{Client, ClientError};
+use crate::logic::msg::{Msg, WindowProps};
+use crate::logic::SurfaceId;
+use crate::playlist::Navigation;
+use crate::prelude::*;
+
+/// How much `zoom in` and `zoom out` change the zoom by.
+const ZOOM_STEP: f32 = 1.25;
+
+const COMMANDS: &[&str] = &[
+    "close", "first", "goto", "help", "last", "next", "open", "previous", "quit", "rotate", "show",
+    "window", "windows", "zoom",
+];
+
+const HELP: &str = "\
+next, previous, first, last   step through the playlist
+goto <n>                      show the nth image of the playlist
+zoom <200%|2|in|out|reset>    zoom the image
+rotate [degrees]              rotate clockwise, 90 degrees unless given
+open <path>                   show a file in a new window
+show <path>                   show a file in the current window
+close [id]                    close a window, the current one unless given
+window <id>                   make another window the current one
+windows                       list the open windows
+quit                          close the console and its windows
+
+on an empty line: left/right step through the playlist, home/end jump to
+either end of it and + - = zoom in, out and back";
+
+#[derive(Debug)]
+pub enum ConsoleError {
+    Terminal,
+    Client,
+}
+
+impl fmt::Display for ConsoleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Terminal => f.write_str("failed to drive the terminal"),
+            Self::Client => f.write_str("failed to pass command to server"),
+        }
+    }
+}
+impl Context for ConsoleError {}
+
+/// What a line typed at the prompt asks for.
+#[derive(Debug)]
+enum Command {
+    /// Passed on to the server as it is.
+    Send(Msg),
+    /// Opens a window showing a file and makes it the current one.
+    Open(PathBuf),
+    /// Shows a file in a window.
+    Show(SurfaceId, PathBuf),
+    Select(u64),
+    List,
+    Help,
+    Quit,
+}
+
+/// Parses a line typed at the prompt, with `current` being the window
+/// commands act on.
+fn parse(line: &str, current: Option<u64>) -> std::result::Result<Command, String> {
+    let line = line.trim();
+    let (name, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let rest = rest.trim();
+
+    let window = || {
+        current
+            .map(SurfaceId::Window)
+            .ok_or_else(|| "no window is selected, pick one with `window <id>`".to_string())
+    };
+    let navigate = |to| -> std::result::Result<Command, String> {
+        Ok(Command::Send(Msg::Navigate { id: window()?, to }))
+    };
+    let path = || match rest {
+        "" => Err(format!("`{name}` needs a path")),
+        path => Ok(PathBuf::from(path)),
+    };
+
+    match name {
+        "next" | "n" => navigate(Navigation::Next),
+        "previous" | "prev" | "p" => navigate(Navigation::Previous),
+        "first" => navigate(Navigation::First),
+        "last" => navigate(Navigation::Last),
+        "goto" => match rest.parse::<usize>() {
+            Ok(n) if n > 0 => navigate(Navigation::Index(n - 1)),
+            _ => Err(format!("expected a position from 1, got {rest:?}")),
+        },
+        "zoom" => {
+            let (factor, relative) = match rest {
+                "in" => (ZOOM_STEP, true),
+                "out" => (ZOOM_STEP.recip(), true),
+                "reset" => (1.0, false),
+                _ => (parse_zoom(rest)?, false),
+            };
+            Ok(Command::Send(Msg::Zoom {
+                id: window()?,
+                factor,
+                relative,
+            }))
+        }
+        "rotate" => {
+            let degrees = match rest {
+                "" => 90.0,
+                _ => rest
+                    .parse()
+                    .map_err(|_| format!("expected degrees, got {rest:?}"))?,
+            };
+            Ok(Command::Send(Msg::Rotate {
+                id: window()?,
+                degrees,
+            }))
+        }
+        "close" => {
+            let id = match rest {
+                "" => window()?,
+                _ => parse_id(rest)?.into(),
+            };
+            Ok(Command::Send(Msg::CloseWindow { id }))
+        }
+        "open" => path().map(Command::Open),
+        "show" => Ok(Command::Show(window()?, path()?)),
+        "window" => parse_id(rest).map(Command::Select),
+        "windows" | "ls" => Ok(Command::List),
+        "help" | "?" => Ok(Command::Help),
+        "quit" | "exit" | "q" => Ok(Command::Quit),
+        _ => Err(format!("unknown command {name:?}, try `help`")),
+    }
+}
+
+/// Reads a zoom as a percentage (`200%`) or a factor (`2`).
+fn parse_zoom(s: &str) -> std::result::Result<f32, String> {
+    let zoom = match s.strip_suffix('%') {
+        Some(percent) => percent.trim().parse::<f32>().map(|p| p / 100.0),
+        None => s.parse::<f32>(),
+    };
+    match zoom {
+        Ok(zoom) if zoom > 0.0 && zoom.is_finite() => Ok(zoom),
+        _ => Err(format!("expected a zoom like `200%` or `2`, got {s:?}")),
+    }
+}
+
+fn parse_id(s: &str) -> std::result::Result<u64, String> {
+    s.parse()
+        .map_err(|_| format!("expected a window id, got {s:?}"))
+}
+
+/// Runs the prompt until it is quit, with `quit` or ctrl-c or ctrl-d.
+///
+/// `current` is the window commands act on to begin with.
+pub fn run(client: Client, current: Option<u64>) -> Result<(), ConsoleError> {
+    let _raw = RawMode::enable().change_context(ConsoleError::Terminal)?;
+
+    let mut console = Console {
+        client,
+        current,
+        prompt: Prompt::default(),
+        said: String::new(),
+        out: io::stdout(),
+    };
+    console.draw().change_context(ConsoleError::Terminal)?;
+
+    loop {
+        let Event::Key(key) = event::read().change_context(ConsoleError::Terminal)? else {
+            continue;
+        };
+        if key.kind == KeyEventKind::Release {
+            continue;
+        }
+
+        match console.key(key) {
+            Ok(true) => {}
+            Ok(false) => break,
+            // the server is still there, it just did not like this one
+            Err(e) if matches!(e.current_context(), ClientError::Rejected(_)) => {
+                console.say(&e.current_context().to_string())
+            }
+            Err(e) => return Err(e).change_context(ConsoleError::Client),
+        }
+        console.draw().change_context(ConsoleError::Terminal)?;
+    }
+
+    console.finish().change_context(ConsoleError::Terminal)
+}
+
+/// Keeps the terminal in raw mode for as long as it lives.
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> io::Result<Self> {
+        terminal::enable_raw_mode()?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+struct Console {
+    client: Client,
+    current: Option<u64>,
+    prompt: Prompt,
+    /// Lines to print above the prompt when it is next drawn.
+    said: String,
+    out: io::Stdout,
+}
+
+impl Console {
+    /// Handles a key press. Returns `false` once the console should close.
+    fn key(&mut self, key: KeyEvent) -> Result<bool, ClientError> {
+        let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        // keys that would do nothing on an empty line act on the window
+        if self.prompt.is_empty() {
+            let binding = match key.code {
+                KeyCode::Right | KeyCode::PageDown => Some("next"),
+                KeyCode::Left | KeyCode::PageUp => Some("previous"),
+                KeyCode::Home => Some("first"),
+                KeyCode::End => Some("last"),
+                KeyCode::Char('+') => Some("zoom in"),
+                KeyCode::Char('-') => Some("zoom out"),
+                KeyCode::Char('=') => Some("zoom reset"),
+                _ => None,
+            };
+            if let Some(line) = binding {
+                return self.line(line);
+            }
+        }
+
+        match key.code {
+            KeyCode::Char('c') | KeyCode::Char('d') if ctrl => return Ok(false),
+            KeyCode::Char('u') if ctrl => self.prompt.clear(),
+            KeyCode::Char(c) if !ctrl => self.prompt.insert(c),
+            KeyCode::Backspace => self.prompt.backspace(),
+            KeyCode::Delete => self.prompt.delete(),
+            KeyCode::Left => self.prompt.left(),
+            KeyCode::Right => self.prompt.right(),
+            KeyCode::Home => self.prompt.cursor = 0,
+            KeyCode::End => self.prompt.cursor = self.prompt.line.len(),
+            KeyCode::Up => self.prompt.older(),
+            KeyCode::Down => self.prompt.newer(),
+            KeyCode::Tab => self.complete(),
+            KeyCode::Enter => {
+                let line = self.prompt.submit();
+                let prompt = self.prompt_text();
+                self.say(&format!("{prompt}{line}"));
+                if !line.trim().is_empty() {
+                    return self.line(&line);
+                }
+            }
+            _ => {}
+        }
+        Ok(true)
+    }
+
+    /// Runs a line as a command. Returns `false` if it quit the console.
+    fn line(&mut self, line: &str) -> Result<bool, ClientError> {
+        let cmd = match parse(line, self.current) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                self.say(&e);
+                return Ok(true);
+            }
+        };
+
+        match cmd {
+            Command::Send(msg) => {
+                let closed = match &msg {
+                    Msg::CloseWindow { id } => Some(id.clone()),
+                    _ => None,
+                };
+                self.client.request(msg)?;
+                if closed.is_some() && closed == self.current.map(SurfaceId::Window) {
+                    self.current = None;
+                }
+            }
+            Command::Open(path) => {
+                let path = absolute(&path)?;
+                let props = WindowProps {
+                    title: path.file_name().map(|n| n.to_string_lossy().into_owned()),
+                    ..Default::default()
+                };
+                let id = self.client.open_window(false, props)?;
+                self.current = Some(id);
+                self.client.request(Msg::ShowImage {
+                    path,
+                    id: id.into(),
+                    placement: None,
+                })?;
+            }
+            Command::Show(id, path) => {
+                let path = absolute(&path)?;
+                self.client.request(Msg::ShowImage {
+                    path,
+                    id,
+                    placement: None,
+                })?;
+            }
+            Command::Select(id) => {
+                // make sure it is there before switching to it
+                self.client.request(Msg::GetWindow { id: id.into() })?;
+                self.current = Some(id);
+            }
+            Command::List => {
+                let windows = self.client.request(Msg::ListWindows {})?;
+                for w in windows.as_array().into_iter().flatten() {
+                    let id = w["id"].as_u64().unwrap_or_default();
+                    let title = w["title"].as_str().unwrap_or_default();
+                    let image = w["image"]["path"].as_str().unwrap_or("-");
+                    self.say(&format!("{id:>4}  {title}  {image}"));
+                }
+            }
+            Command::Help => self.say(HELP),
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// Completes the command name or path being typed.
+    fn complete(&mut self) {
+        let line: String = self.prompt.line[..self.prompt.cursor].iter().collect();
+        let (word, candidates) = match line.split_once(' ') {
+            None => (
+                line.as_str(),
+                COMMANDS
+                    .iter()
+                    .filter(|c| c.starts_with(line.as_str()))
+                    .map(|c| c.to_string())
+                    .collect(),
+            ),
+            Some((_, rest)) => (rest.trim_start(), complete_path(rest.trim_start())),
+        };
+
+        let prefix = common_prefix(&candidates);
+        if prefix.len() > word.len() {
+            for c in prefix[word.len()..].chars() {
+                self.prompt.insert(c);
+            }
+            // a whole command name is followed by its argument
+            if candidates.len() == 1 && !line.contains(' ') {
+                self.prompt.insert(' ');
+            }
+        } else if candidates.len() > 1 {
+            let names: Vec<&str> = candidates
+                .iter()
+                .map(|c| match c.trim_end_matches('/').rfind('/') {
+                    Some(i) => &c[i + 1..],
+                    None => c,
+                })
+                .collect();
+            self.say(&names.join("  "));
+        }
+    }
+
+    /// Prints `text` above the prompt the next time it is drawn.
+    fn say(&mut self, text: &str) {
+        // raw mode does not turn newlines into line starts by itself
+        for line in text.lines() {
+            self.said.push_str(line);
+            self.said.push_str("\r\n");
+        }
+    }
+
+    /// Redraws the prompt and the line being typed, after anything said
+    /// since it was last drawn.
+    fn draw(&mut self) -> io::Result<()> {
+        let prompt = self.prompt_text();
+        let line: String = self.prompt.line.iter().collect();
+        let column = prompt.chars().count() + self.prompt.cursor;
+        queue!(
+            self.out,
+            cursor::MoveToColumn(0),
+            terminal::Clear(terminal::ClearType::CurrentLine),
+            style::Print(std::mem::take(&mut self.said)),
+            style::Print(prompt),
+            style::Print(line),
+            cursor::MoveToColumn(column.try_into().unwrap_or(u16::MAX)),
+        )?;
+        self.out.flush()
+    }
+
+    /// Leaves the terminal on a fresh line.
+    fn finish(&mut self) -> io::Result<()> {
+        queue!(self.out, style::Print("\r\n"))?;
+        self.out.flush()
+    }
+
+    fn prompt_text(&self) -> String {
+        match self.current {
+            Some(id) => format!("imvr[{id}]> "),
+            None => "imvr> ".to_string(),
+        }
+    }
+}
+
+/// The server does not share our working directory.
+fn absolute(path: &Path) -> Result<PathBuf, ClientError> {
+    path.canonicalize()
+        .map_err(|e| Report::new(ClientError::Rejected(format!("{path:?}: {e}"))))
+}
+
+/// The line being typed along with the lines typed before it.
+#[derive(Default)]
+struct Prompt {
+    line: Vec<char>,
+    cursor: usize,
+    history: Vec<String>,
+    /// Which line of history is being shown, if any.
+    browsing: Option<usize>,
+    /// What was typed before browsing the history.
+    draft: Vec<char>,
+}
+
+impl Prompt {
+    fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    fn insert(&mut self, c: char) {
+        self.line.insert(self.cursor, c);
+        self.cursor += 1;
+    }
+
+    fn backspace(&mut self) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            self.line.remove(self.cursor);
+        }
+    }
+
+    fn delete(&mut self) {
+        if self.cursor < self.line.len() {
+            self.line.remove(self.cursor);
+        }
+    }
+
+    fn left(&mut self) {
+        self.cursor = self.cursor.saturating_sub(1);
+    }
+
+    fn right(&mut self) {
+        self.cursor = (self.cursor + 1).min(self.line.len());
+    }
+
+    fn clear(&mut self) {
+        self.line.clear();
+        self.cursor = 0;
+    }
+
+    fn set(&mut self, line: Vec<char>) {
+        self.cursor = line.len();
+        self.line = line;
+    }
+
+    /// Shows the line entered before the one being shown.
+    fn older(&mut self) {
+        let next = match self.browsing {
+            Some(0) => return,
+            Some(i) => i - 1,
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.line.clone();
+                self.history.len() - 1
+            }
+        };
+        self.browsing = Some(next);
+        self.set(self.history[next].chars().collect());
+    }
+
+    /// Shows the line entered after the one being shown, or what was being
+    /// typed before browsing.
+    fn newer(&mut self) {
+        let Some(i) = self.browsing else { return };
+        if i + 1 < self.history.len() {
+            self.browsing = Some(i + 1);
+            self.set(self.history[i + 1].chars().collect());
+        } else {
+            self.browsing = None;
+            let draft = std::mem::take(&mut self.draft);
+            self.set(draft);
+        }
+    }
+
+    /// Takes the line, remembering it unless it is blank or a repeat.
+    fn submit(&mut self) -> String {
+        let line: String = self.line.drain(..).collect();
+        self.cursor = 0;
+        self.browsing = None;
+        if !line.trim().is_empty() && self.history.last() != Some(&line) {
+            self.history.push(line.clone());
+        }
+        line
+    }
+}
+
+/// The paths that start with `prefix`, with a `/` after directories.
+///
+/// Hidden files are left out unless `prefix` names one.
+fn complete_path(prefix: &str) -> Vec<String> {
+    let (dir, name) = match prefix.rfind('/') {
+        Some(i) => prefix.split_at(i + 1),
+        None => ("", prefix),
+    };
+    let read = match dir {
+        "" => Path::new("."),
+        dir => Path::new(dir),
+    };
+
+    let mut paths: Vec<String> = fs::read_dir(read)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter_map(|entry| {
+            let file = entry.file_name().into_string().ok()?;
+            if !file.starts_with(name) || (file.starts_with('.') && !name.starts_with('.')) {
+                return None;
+            }
+            let slash = if entry.path().is_dir() { "/" } else { "" };
+            Some(format!("{dir}{file}{slash}"))
+        })
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// The longest string every candidate starts with.
+fn common_prefix(candidates: &[String]) -> &str {
+    let Some((first, rest)) = candidates.split_first() else {
+        return "";
+    };
+    let mut len = first.len();
+    for c in rest {
+        len = first
+            .char_indices()
+            .zip(c.chars())
+            .find(|((_, a), b)| a != b)
+            .map_or(len.min(c.len()), |((i, _), _)| i.min(len));
+    }
+    &first[..len]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::SurfaceId;
+
+    #[test]
+    fn parses_window_commands() {
+        match parse("zoom 200%", Some(3)) {
+            Ok(Command::Send(Msg::Zoom {
+                id,
+                factor,
+                relative: false,
+            })) => {
+                assert_eq!(id, SurfaceId::Window(3));
+                assert_eq!(factor, 2.0);
+            }
+            cmd => panic!("unexpected {cmd:?}"),
+        }
+        assert!(matches!(
+            parse("  rotate ", Some(1)),
+            Ok(Command::Send(Msg::Rotate { degrees, .. })) if degrees == 90.0
+        ));
+        assert!(matches!(
+            parse("goto 2", Some(1)),
+            Ok(Command::Send(Msg::Navigate {
+                to: Navigation::Index(1),
+                ..
+            }))
+        ));
+        assert!(matches!(
+            parse("close 2", None),
+            Ok(Command::Send(Msg::CloseWindow {
+                id: SurfaceId::Window(2)
+            }))
+        ));
+        assert!(matches!(
+            parse("open my picture.png", None),
+            Ok(Command::Open(path)) if path == Path::new("my picture.png")
+        ));
+    }
+
+    #[test]
+    fn rejects_bad_commands() {
+        assert!(parse("next", None).is_err());
+        assert!(parse("zoom -1", Some(1)).is_err());
+        assert!(parse("goto 0", Some(1)).is_err());
+        assert!(parse("open", Some(1)).is_err());
+        assert!(parse("frobnicate", Some(1)).is_err());
+    }
+
+    #[test]
+    fn history_walks_back_and_returns_to_the_draft() {
+        let mut prompt = Prompt::default();
+        for line in ["next", "zoom in", "zoom in"] {
+            line.chars().for_each(|c| prompt.insert(c));
+            prompt.submit();
+        }
+        assert_eq!(prompt.history, ["next", "zoom in"]);
+
+        "ro".chars().for_each(|c| prompt.insert(c));
+        prompt.older();
+        prompt.older();
+        prompt.older();
+        assert_eq!(prompt.line.iter().collect::<String>(), "next");
+        prompt.newer();
+        prompt.newer();
+        assert_eq!(prompt.line.iter().collect::<String>(), "ro");
+    }
+
+    #[test]
+    fn common_prefix_stops_at_first_difference() {
+        let paths = ["img/a1.png", "img/a2.png", "img/a"].map(String::from);
+        assert_eq!(common_prefix(&paths), "img/a");
+        assert_eq!(common_prefix(&paths[..1]), "img/a1.png");
+        assert_eq!(common_prefix(&[]), "");
+    }
+}
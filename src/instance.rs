@@ -0,0 +1,147 @@
+//! Finding and claiming the socket a server listens on.
+//!
+//! Every user gets their own socket under `$XDG_RUNTIME_DIR` (or a private
+//! directory in `/tmp`) and several servers can run side by side by giving
+//! them different instance names. A lock file next to the socket makes sure
+//! only one server ever listens on a given path.
+
+use std::fs::{self, DirBuilder, File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use crate::prelude::*;
+
+/// Environment variable that overrides the socket path.
+pub const SOCKET_ENV: &str = "IMVR_SOCKET";
+
+/// Works out which socket to use.
+///
+/// An explicit path wins, then `$IMVR_SOCKET`, then `<name>.sock` in the per
+/// user runtime directory.
+pub fn socket_path(explicit: Option<&Path>, name: &str) -> PathBuf {
+    if let Some(path) = explicit {
+        return path.to_path_buf();
+    }
+
+    if let Some(path) = std::env::var_os(SOCKET_ENV).filter(|p| !p.is_empty()) {
+        return path.into();
+    }
+
+    runtime_dir().join(format!("{name}.sock"))
+}
+
+/// The directory sockets live in when no path is given.
+fn runtime_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR").filter(|p| !p.is_empty()) {
+        Some(dir) => PathBuf::from(dir).join("imvr"),
+        None => {
+            // # Safety
+            // getuid can not fail and has no preconditions
+            let uid = unsafe { libc::getuid() };
+            PathBuf::from(format!("/tmp/imvr-{uid}"))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InstanceError {
+    Io,
+    AlreadyRunning(PathBuf),
+}
+
+impl fmt::Display for InstanceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io => f.write_str("failed to prepare the socket"),
+            Self::AlreadyRunning(path) => {
+                write!(f, "another server is already listening on {path:?}")
+            }
+        }
+    }
+}
+impl Context for InstanceError {}
+
+/// Proof that this process is the only server for a socket path.
+///
+/// Holds an exclusive lock on `<socket>.lock` for as long as it lives and
+/// removes the socket when dropped.
+#[derive(Debug)]
+pub struct InstanceLock {
+    socket: PathBuf,
+    _lock: File,
+}
+
+impl InstanceLock {
+    /// Claims `socket` for this process, clearing out a socket left behind by
+    /// a server that did not shut down cleanly.
+    pub fn acquire(socket: &Path) -> Result<Self, InstanceError> {
+        if let Some(dir) = socket.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .attach_printable_lazy(|| format!("could not create {dir:?}"))
+                .change_context(InstanceError::Io)?;
+        }
+
+        let lock_path = socket.with_extension("lock");
+        let lock = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)
+            .attach_printable_lazy(|| format!("could not open {lock_path:?}"))
+            .change_context(InstanceError::Io)?;
+
+        // # Safety
+        // the fd is owned by `lock` which outlives this call
+        let res = unsafe { libc::flock(lock.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if res == -1 {
+            let e = io::Error::last_os_error();
+            return match e.kind() {
+                io::ErrorKind::WouldBlock => Err(Report::new(InstanceError::AlreadyRunning(
+                    socket.to_path_buf(),
+                ))),
+                _ => Err(Report::new(e)).change_context(InstanceError::Io),
+            };
+        }
+
+        if socket.exists() {
+            // a server that predates the lock file could still be on it
+            if UnixStream::connect(socket).is_ok() {
+                return Err(Report::new(InstanceError::AlreadyRunning(
+                    socket.to_path_buf(),
+                )));
+            }
+
+            log::warn!("removing stale socket {socket:?}");
+            fs::remove_file(socket)
+                .attach_printable_lazy(|| format!("could not remove stale socket {socket:?}"))
+                .change_context(InstanceError::Io)?;
+        }
+
+        Ok(Self {
+            socket: socket.to_path_buf(),
+            _lock: lock,
+        })
+    }
+
+    /// The socket this lock guards.
+    #[inline]
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.socket) {
+            if e.kind() != io::ErrorKind::NotFound {
+                log::warn!("failed to remove socket {:?}: {e}", self.socket);
+            }
+        }
+    }
+}
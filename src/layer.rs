@@ -0,0 +1,274 @@
+//! A stand in for `ueberzug layer`.
+//!
+//! Reads ueberzug commands from stdin and turns them into requests to a
+//! server so file managers that already know how to drive ueberzug can show
+//! previews with imvr. Every `identifier` gets its own window which closes
+//! with a `remove` or once stdin ends.
+//!
+//! Placement is given in cells of the terminal and turned into pixels with
+//! the size of its cells. Where the terminal itself is on screen is not known
+//! so positions are counted from the top left of the screen.
+
+use std::collections::HashMap;
+use std::io::{self, BufRead};
+use std::path::PathBuf;
+
+use serde::Deserialize;
+
+use crate::client::{Client, ClientError};
+use crate::logic::msg::{Msg, Position, Size, WindowProps};
+use crate::prelude::*;
+use crate::terminal::cell_size;
+
+/// The formats ueberzug accepts commands in.
+#[derive(Debug, Clone, Copy, Default, clap::ValueEnum)]
+pub enum LayerParser {
+    /// One json object per line.
+    #[default]
+    Json,
+    /// Tab separated keys and values on one line.
+    Simple,
+}
+
+#[derive(Debug)]
+pub enum LayerError {
+    Stdin,
+    Client,
+}
+
+impl fmt::Display for LayerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdin => f.write_str("failed to read commands from stdin"),
+            Self::Client => f.write_str("failed to pass command to server"),
+        }
+    }
+}
+impl Context for LayerError {}
+
+/// A ueberzug command.
+///
+/// Fields ueberzug knows that imvr has no use for (`scaler`, `draw`, ...) are
+/// ignored.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Command {
+    Add {
+        identifier: String,
+        path: PathBuf,
+        #[serde(flatten)]
+        place: Place,
+    },
+    Remove {
+        identifier: String,
+    },
+}
+
+/// Where an image is to be shown, in cells of the terminal.
+#[derive(Debug, Default, PartialEq, Eq, Deserialize)]
+struct Place {
+    x: Option<i32>,
+    y: Option<i32>,
+    max_width: Option<u32>,
+    max_height: Option<u32>,
+}
+
+/// The keys whose values the simple parser reads as numbers.
+const NUMERIC: [&str; 4] = ["x", "y", "max_width", "max_height"];
+
+impl LayerParser {
+    fn parse(self, line: &str) -> Result<Command, json::Error> {
+        match self {
+            LayerParser::Json => json::from_str(line),
+            LayerParser::Simple => {
+                let mut fields = line.split('\t');
+                let mut map = json::Map::new();
+                while let (Some(key), Some(value)) = (fields.next(), fields.next()) {
+                    // anything else stays a string, even paths made of digits
+                    let value = match (NUMERIC.contains(&key), value.parse::<i64>()) {
+                        (true, Ok(n)) => n.into(),
+                        _ => value.into(),
+                    };
+                    map.insert(key.to_string(), value);
+                }
+                json::from_value(map.into())
+            }
+        }
+    }
+}
+
+impl Place {
+    /// The props of a window covering this place, titled `title`, given the
+    /// width and height of a cell in pixels.
+    fn props(&self, title: String, cell: (u32, u32)) -> WindowProps {
+        let (width, height) = cell;
+        let px = |cells: i32, size: u32| cells.saturating_mul(size.try_into().unwrap_or(i32::MAX));
+
+        WindowProps {
+            title: Some(title),
+            position: self.x.zip(self.y).map(|(x, y)| Position {
+                x: px(x, width),
+                y: px(y, height),
+            }),
+            size: self.max_width.zip(self.max_height).map(|(w, h)| Size {
+                width: w.saturating_mul(width),
+                height: h.saturating_mul(height),
+            }),
+            ..Default::default()
+        }
+    }
+}
+
+/// Translates commands from stdin until it closes.
+pub fn run(client: Client, parser: LayerParser) -> Result<(), LayerError> {
+    let mut layer = Layer {
+        client,
+        windows: HashMap::new(),
+    };
+
+    for line in io::stdin().lock().lines() {
+        let line = line.change_context(LayerError::Stdin)?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let cmd = match parser.parse(&line) {
+            Ok(cmd) => cmd,
+            Err(e) => {
+                log::warn!("ignoring malformed command {line:?}: {e}");
+                continue;
+            }
+        };
+
+        match layer.command(cmd) {
+            Ok(()) => {}
+            // the server is still there, it just did not like this one
+            Err(e) if matches!(e.current_context(), ClientError::Rejected(_)) => {
+                log::warn!("{e}")
+            }
+            Err(e) => return Err(e).change_context(LayerError::Client),
+        }
+    }
+
+    // our windows close with the connection
+    Ok(())
+}
+
+struct Layer {
+    client: Client,
+    /// The window shown for each identifier.
+    windows: HashMap<String, u64>,
+}
+
+impl Layer {
+    fn command(&mut self, cmd: Command) -> Result<(), ClientError> {
+        match cmd {
+            Command::Add {
+                identifier,
+                path,
+                place,
+            } => self.add(identifier, path, &place),
+            Command::Remove { identifier } => {
+                let Some(id) = self.windows.remove(&identifier) else {
+                    return Ok(());
+                };
+                self.client
+                    .request(Msg::CloseWindow { id: id.into() })
+                    .map(|_| ())
+            }
+        }
+    }
+
+    fn add(&mut self, identifier: String, path: PathBuf, place: &Place) -> Result<(), ClientError> {
+        // the server does not share our working directory
+        let path = path
+            .canonicalize()
+            .map_err(|e| Report::new(ClientError::Rejected(format!("{path:?}: {e}"))))?;
+        let props = place.props(identifier.clone(), cell_size());
+
+        if let Some(&id) = self.windows.get(&identifier) {
+            let moved = Msg::SetWindowProps {
+                id: id.into(),
+                props: props.clone(),
+            };
+            let show = Msg::ShowImage {
+                path: path.clone(),
+                id: id.into(),
+                placement: None,
+            };
+            let res = self
+                .client
+                .request(moved)
+                .and_then(|_| self.client.request(show));
+            match res {
+                Ok(_) => return Ok(()),
+                // most likely the window was closed by hand so make a new one
+                Err(e) if matches!(e.current_context(), ClientError::Rejected(_)) => {
+                    log::info!("window for {identifier:?} is gone: {e}");
+                    self.windows.remove(&identifier);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        let id = self.client.open_window(false, props)?;
+        self.windows.insert(identifier, id);
+        self.client
+            .request(Msg::ShowImage {
+                path,
+                id: id.into(),
+                placement: None,
+            })
+            .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn simple_keeps_strings_that_look_like_numbers() {
+        let line = "action\tadd\tidentifier\t1\tx\t2\ty\t3\tpath\t/tmp/42";
+        match LayerParser::Simple.parse(line).unwrap() {
+            Command::Add {
+                identifier,
+                path,
+                place,
+            } => {
+                assert_eq!(identifier, "1");
+                assert_eq!(path, PathBuf::from("/tmp/42"));
+                let expected = Place {
+                    x: Some(2),
+                    y: Some(3),
+                    ..Default::default()
+                };
+                assert_eq!(place, expected);
+            }
+            cmd => panic!("expected Add, got {cmd:?}"),
+        }
+    }
+
+    #[test]
+    fn places_windows_by_cell() {
+        let place = Place {
+            x: Some(2),
+            y: Some(1),
+            max_width: Some(40),
+            max_height: Some(10),
+        };
+        let props = place.props("preview".into(), (8, 16));
+        assert_eq!(props.title.as_deref(), Some("preview"));
+        assert_eq!(props.position, Some(Position { x: 16, y: 16 }));
+        assert_eq!(
+            props.size,
+            Some(Size {
+                width: 320,
+                height: 160
+            })
+        );
+
+        let props = Place::default().props("preview".into(), (8, 16));
+        assert!(props.position.is_none() && props.size.is_none());
+    }
+}
@@ -1,27 +1,27 @@
-use ext::parse::MoveIt;
+use crate::prelude::*;
 
-#[derive(Debug, serde::Serialize, serde::Deserialize)]
+/// How a client refers to a surface.
+///
+/// Window ids are handed out by imvr when a window opens and have nothing to
+/// do with the ids of the windowing backend.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum SurfaceId {
     Terminal,
     Window(u64),
+    /// The name a window was given when it was opened.
+    Name(String),
 }
 
-impl SurfaceId {
-    pub fn as_id(&self) -> Option<u64> {
+impl fmt::Display for SurfaceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            SurfaceId::Terminal => None,
-            SurfaceId::Window(id) => Some(*id),
+            SurfaceId::Terminal => f.write_str("terminal"),
+            SurfaceId::Window(id) => write!(f, "window {id}"),
+            SurfaceId::Name(name) => write!(f, "window {name:?}"),
         }
     }
 }
 
-impl From<winit::window::WindowId> for SurfaceId {
-    #[inline]
-    fn from(value: winit::window::WindowId) -> Self {
-        value.move_it(u64::from).into()
-    }
-}
-
 impl From<u64> for SurfaceId {
     #[inline]
     fn from(value: u64) -> Self {
@@ -1,23 +1,98 @@
-// #[derive(Debug)]
-// pub enum FocusChange {
-//     Gained,
-//     Lost,
-// }
-//
-// impl FocusChange {
-//     #[inline]
-//     pub fn did_gain(&self) -> bool {
-//         matches!(self, Self::Gained)
-//     }
-// }
-//
-// impl From<bool> for FocusChange {
-//     #[inline]
-//     fn from(value: bool) -> Self {
-//         match value {
-//             true => FocusChange::Gained,
-//             false => FocusChange::Lost,
-//         }
-//     }
-// }
-//
+use crate::prelude::*;
+
+use serde::{Deserialize, Serialize};
+
+/// Something that happened to a window, as told to subscribed clients.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Event {
+    pub window: u64,
+    #[serde(flatten)]
+    pub kind: EventKind,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum EventKind {
+    Opened,
+    Closed,
+    Key { key: String },
+    Resized { width: u32, height: u32 },
+    Focus { change: FocusChange },
+}
+
+impl EventKind {
+    /// The filter that selects this kind of event.
+    pub fn filter(&self) -> EventFilter {
+        match self {
+            EventKind::Opened => EventFilter::Opened,
+            EventKind::Closed => EventFilter::Closed,
+            EventKind::Key { .. } => EventFilter::Key,
+            EventKind::Resized { .. } => EventFilter::Resized,
+            EventKind::Focus { .. } => EventFilter::Focus,
+        }
+    }
+}
+
+/// The kinds of [`Event`] a client can subscribe to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventFilter {
+    Opened,
+    Closed,
+    Key,
+    Resized,
+    Focus,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FocusChange {
+    Gained,
+    Lost,
+}
+
+impl FocusChange {
+    #[inline]
+    pub fn did_gain(&self) -> bool {
+        matches!(self, Self::Gained)
+    }
+}
+
+impl From<bool> for FocusChange {
+    #[inline]
+    fn from(value: bool) -> Self {
+        match value {
+            true => FocusChange::Gained,
+            false => FocusChange::Lost,
+        }
+    }
+}
+
+impl<'a> SomeFrom<&'a winit::event::WindowEvent> for EventKind {
+    fn some_from(value: &'a winit::event::WindowEvent) -> Option<Self> {
+        use winit::event::ElementState;
+        use winit::event::WindowEvent as We;
+        use winit::keyboard::Key;
+
+        let kind = match value {
+            We::KeyboardInput { event, .. } if event.state == ElementState::Pressed => {
+                let key = match &event.logical_key {
+                    Key::Character(c) => c.to_string(),
+                    Key::Named(n) => format!("{n:?}"),
+                    _ => return None,
+                };
+                EventKind::Key { key }
+            }
+            We::Resized(size) => EventKind::Resized {
+                width: size.width,
+                height: size.height,
+            },
+            We::Focused(focused) => EventKind::Focus {
+                change: (*focused).into(),
+            },
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+}
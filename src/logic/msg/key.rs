@@ -1,12 +1,22 @@
+use crate::playlist::Navigation;
 use crate::prelude::*;
+use winit::window::WindowId;
 
-impl SomeFrom<Key> for WindowMsg {
-    fn some_from(value: Key) -> Option<Self> {
-        match value {
-            Key::Char('q') => Some(Self::Exit),
-            Key::Char('l') => todo!("Select Next Image"),
+impl Key {
+    /// What pressing this key in a window does.
+    ///
+    /// Quitting only closes that window, which takes the server down with it
+    /// if it was the last one and the server does not persist.
+    pub fn action(self, id: WindowId) -> Option<WindowMsg> {
+        let navigate = |to| Some(WindowMsg::NavigateWindow { id, to });
+        match self {
+            Key::Char('q') => Some(WindowMsg::CloseRequested { id }),
+            Key::Char('l' | 'n' | ' ') | Key::Right => navigate(Navigation::Next),
+            Key::Char('h' | 'p') | Key::Left => navigate(Navigation::Previous),
+            Key::Char('g') | Key::Home => navigate(Navigation::First),
+            Key::Char('G') | Key::End => navigate(Navigation::Last),
             Key::Char(_) => None,
-            Key::Ctrl('c') => Some(Self::Exit),
+            Key::Ctrl('c') => Some(WindowMsg::CloseRequested { id }),
             Key::Ctrl(_) => None,
             Key::Alt(_) => None,
         }
@@ -18,19 +28,56 @@ pub enum Key {
     Char(char),
     Ctrl(char),
     Alt(char),
+    Left,
+    Right,
+    Home,
+    End,
 }
 
 use winit::event::KeyEvent as WinitKeyEvent;
+use winit::keyboard::{Key as WinitKey, ModifiersState};
 
-impl SomeFrom<WinitKeyEvent> for Key {
-    fn some_from(value: WinitKeyEvent) -> Option<Self> {
+/// A key pressed in a window along with the modifiers held down at the time,
+/// which winit only reports in separate events.
+impl SomeFrom<(WinitKeyEvent, ModifiersState)> for Key {
+    fn some_from((event, modifiers): (WinitKeyEvent, ModifiersState)) -> Option<Self> {
         use winit::event::ElementState;
-        use winit::keyboard::{KeyCode as WinitKeyCode, PhysicalKey};
-        match (value.physical_key, value.repeat, value.state) {
-            (PhysicalKey::Code(WinitKeyCode::KeyQ), _, ElementState::Pressed) => {
-                Some(Key::Char('q'))
+
+        match event.state {
+            ElementState::Pressed => Key::from_winit(event.logical_key, modifiers),
+            ElementState::Released => None,
+        }
+    }
+}
+
+impl Key {
+    /// Keys held with ctrl or alt become [`Key::Ctrl`] and [`Key::Alt`]. Any
+    /// other combination of modifiers besides shift is ignored.
+    fn from_winit(key: WinitKey, modifiers: ModifiersState) -> Option<Self> {
+        use winit::keyboard::NamedKey;
+
+        let key = match key {
+            WinitKey::Character(s) => {
+                let mut chars = s.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => Key::Char(c),
+                    _ => return None,
+                }
             }
-            (_, _, _) => None,
+            WinitKey::Named(NamedKey::Space) => Key::Char(' '),
+            WinitKey::Named(NamedKey::ArrowLeft) => Key::Left,
+            WinitKey::Named(NamedKey::ArrowRight) => Key::Right,
+            WinitKey::Named(NamedKey::Home) => Key::Home,
+            WinitKey::Named(NamedKey::End) => Key::End,
+            _ => return None,
+        };
+
+        let modifiers = modifiers.difference(ModifiersState::SHIFT);
+        match key {
+            _ if modifiers.is_empty() => Some(key),
+            Key::Char(c) if modifiers == ModifiersState::CONTROL => Some(Key::Ctrl(c)),
+            Key::Char(c) if modifiers == ModifiersState::ALT => Some(Key::Alt(c)),
+            _ => None,
         }
     }
 }
@@ -48,8 +95,59 @@ impl SomeFrom<CrossKeyEvent> for Key {
         match (code, modifiers) {
             (Kc::Char(c), Km::CONTROL) => Some(Key::Ctrl(c)),
             (Kc::Char(c), Km::ALT) => Some(Key::Alt(c)),
-            (Kc::Char(c), Km::NONE) => Some(Key::Char(c)),
+            (Kc::Char(c), Km::NONE | Km::SHIFT) => Some(Key::Char(c)),
+            (Kc::Left, _) => Some(Key::Left),
+            (Kc::Right, _) => Some(Key::Right),
+            (Kc::Home, _) => Some(Key::Home),
+            (Kc::End, _) => Some(Key::End),
             (_, _) => None,
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn window_keys_keep_their_modifiers() {
+        let c = || WinitKey::Character("c".into());
+        let key = |key, modifiers| Key::from_winit(key, modifiers);
+
+        assert!(matches!(
+            key(c(), ModifiersState::empty()),
+            Some(Key::Char('c'))
+        ));
+        assert!(matches!(
+            key(c(), ModifiersState::CONTROL),
+            Some(Key::Ctrl('c'))
+        ));
+        assert!(matches!(key(c(), ModifiersState::ALT), Some(Key::Alt('c'))));
+        assert!(matches!(
+            key(WinitKey::Character("G".into()), ModifiersState::SHIFT),
+            Some(Key::Char('G'))
+        ));
+
+        // other modifiers, or ctrl and alt together, are left to the system
+        assert!(key(c(), ModifiersState::SUPER).is_none());
+        assert!(key(c(), ModifiersState::CONTROL | ModifiersState::ALT).is_none());
+
+        // ctrl-n and alt-l are not just n and l, so they do not navigate
+        let id = unsafe { WindowId::dummy() };
+        for (c, modifiers) in [('n', ModifiersState::CONTROL), ('l', ModifiersState::ALT)] {
+            let pressed = key(WinitKey::Character(c.to_string().into()), modifiers);
+            assert!(pressed.unwrap().action(id).is_none());
+        }
+    }
+
+    #[test]
+    fn quitting_closes_only_the_window() {
+        let id = unsafe { WindowId::dummy() };
+        for key in [Key::Char('q'), Key::Ctrl('c')] {
+            assert!(matches!(
+                key.action(id),
+                Some(WindowMsg::CloseRequested { id: closed }) if closed == id
+            ));
+        }
+    }
+}
@@ -1,40 +1,185 @@
 use crate::prelude::*;
 
-// mod event;
+pub mod event;
 mod key;
+mod props;
+mod reply;
 mod source;
 mod terminal;
 mod window;
 
-use serde::Deserialize;
-use tokio::sync::oneshot;
-use std::os::fd::RawFd;
+use serde::{Deserialize, Serialize};
+use std::os::fd::OwnedFd;
 use std::path::PathBuf;
+use tokio::sync::oneshot;
 
-pub use self::source::{EventHandler, EventSendError};
+pub use self::event::{Event, EventFilter, EventKind};
+pub use self::props::{Fit, Position, Size, WindowProps};
+pub use self::reply::{Reply, Response, ResponseBody};
+pub use self::source::{Args, Command, EventHandler, EventSendError, Launch, LaunchError};
 pub use self::{terminal::TerminalMsg, window::WindowMsg};
 use super::SurfaceId;
+use crate::playlist::Navigation;
+use crate::render::gpu::image::PixelFormat;
+use crate::terminal::Placement;
 
-#[derive(Deserialize)]
+#[rustfmt::skip]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Msg {
-    ShowImage { path: PathBuf, id: SurfaceId },
-    OpenWindow { resp: Option<ReturnAddress> },
+    /// Shows the image at `path`.
+    ///
+    /// Every way of showing an image takes a `placement`, which says where it
+    /// goes when sent to [`SurfaceId::Terminal`] and is ignored by windows.
+    ShowImage {
+        path: PathBuf,
+        id: SurfaceId,
+        #[serde(default)]
+        placement: Option<Placement>,
+    },
+    /// Shows an encoded image (png, jpeg, ...) sent as base64.
+    ShowImageData {
+        #[serde(with = "crate::util::base64")]
+        data: Vec<u8>,
+        id: SurfaceId,
+        #[serde(default)]
+        placement: Option<Placement>,
+    },
+    /// Shows pixels sent as base64, with rows `stride` bytes apart or
+    /// tightly packed.
+    ShowImageRaw {
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        #[serde(default)]
+        stride: Option<u32>,
+        #[serde(with = "crate::util::base64")]
+        data: Vec<u8>,
+        id: SurfaceId,
+        #[serde(default)]
+        placement: Option<Placement>,
+    },
+    /// Opens a new window and answers with its id.
+    ///
+    /// Windows opened over a socket are closed when that connection ends
+    /// unless they are `detached`. A `name` lets later requests use
+    /// [`SurfaceId::Name`] and must not already be taken.
+    OpenWindow {
+        #[serde(default)]
+        detached: bool,
+        #[serde(default)]
+        name: Option<String>,
+        #[serde(flatten)]
+        props: WindowProps,
+    },
+    /// Shows pixels from a memfd sent alongside the request over the socket,
+    /// mapping it rather than copying it. The memfd has to be sealed with
+    /// `F_SEAL_SHRINK`.
+    ///
+    /// `stride` is the number of bytes per row and defaults to tightly packed
+    /// rows. `offset` is where the first row starts in the file.
+    ShowImageFd {
+        width: u32,
+        height: u32,
+        format: PixelFormat,
+        #[serde(default)]
+        stride: Option<u32>,
+        #[serde(default)]
+        offset: u64,
+        id: SurfaceId,
+        #[serde(default)]
+        placement: Option<Placement>,
+        /// Filled in by the connection the request arrived on.
+        #[serde(skip)]
+        fd: Option<OwnedFd>,
+    },
+    CloseWindow { id: SurfaceId },
+    /// Gives a window a list of images to step through and shows the one at
+    /// `index`.
+    SetPlaylist {
+        id: SurfaceId,
+        paths: Vec<PathBuf>,
+        #[serde(default)]
+        index: usize,
+    },
+    /// Moves a window to another image in its playlist.
+    Navigate { id: SurfaceId, to: Navigation },
+    /// Zooms the image in a window, 1 being the size it is placed at. With
+    /// `relative` the zoom so far is multiplied by `factor` instead.
+    Zoom {
+        id: SurfaceId,
+        factor: f32,
+        #[serde(default)]
+        relative: bool,
+    },
+    /// Rotates the image in a window clockwise by `degrees`.
+    Rotate { id: SurfaceId, degrees: f32 },
+    SetWindowProps {
+        id: SurfaceId,
+        #[serde(flatten)]
+        props: WindowProps,
+    },
+    /// Answers with a description of every open window.
+    ListWindows {},
+    /// Answers with a description of one window.
+    GetWindow { id: SurfaceId },
+    /// Closes every window and stops the server.
+    Shutdown {},
+    /// Start sending [`Event`]s of the given kinds (or all of them if empty)
+    /// to this connection. Handled by the connection itself.
+    Subscribe {
+        #[serde(default)]
+        events: Vec<EventFilter>,
+    },
 }
 
-impl Msg {
+/// A [`Msg`] along with where its outcome should be reported.
+#[derive(Debug)]
+pub struct Request {
+    pub msg: Msg,
+    pub resp: Option<ReturnAddress>,
+}
+
+impl Request {
     #[inline]
-    pub fn open(sender: oneshot::Sender<u64>) -> Self {
-        Self::OpenWindow {
+    pub fn new(msg: Msg) -> Self {
+        Self { msg, resp: None }
+    }
+
+    #[inline]
+    pub fn open(
+        name: Option<String>,
+        props: WindowProps,
+        sender: oneshot::Sender<std::result::Result<u64, String>>,
+    ) -> Self {
+        Self {
+            msg: Msg::OpenWindow {
+                detached: true,
+                name,
+                props,
+            },
             resp: Some(ReturnAddress::Memory(sender)),
         }
     }
 }
 
-#[derive(Deserialize)]
-#[serde(from = "RawFd")]
+/// The json form of a [`Request`] as it is read from a socket.
+///
+/// `request_id` is chosen by the client and echoed back in the [`Response`].
+#[derive(Debug, Serialize, Deserialize)]
+pub struct RawRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+    #[serde(flatten)]
+    pub msg: Msg,
+}
+
 pub enum ReturnAddress {
-    Memory(oneshot::Sender<u64>),
-    File(RawFd),
+    /// Gets the id of a new surface or why it could not be made.
+    Memory(oneshot::Sender<std::result::Result<u64, String>>),
+    /// Learns when a request that gives nothing back is done, or why it
+    /// failed.
+    Done(oneshot::Sender<std::result::Result<(), String>>),
+    Socket(Reply),
 }
 
 impl fmt::Debug for ReturnAddress {
@@ -42,36 +187,76 @@ impl fmt::Debug for ReturnAddress {
         match self {
             Self::Memory(_) => f
                 .debug_tuple("Memory")
-                .field(&"oneshot::Sender<u64>")
+                .field(&"oneshot::Sender<std::result::Result<u64, String>>")
                 .finish(),
-            Self::File(fd) => f.debug_tuple("File").field(fd).finish(),
+            Self::Done(_) => f
+                .debug_tuple("Done")
+                .field(&"oneshot::Sender<std::result::Result<(), String>>")
+                .finish(),
+            Self::Socket(r) => f.debug_tuple("Socket").field(r).finish(),
         }
     }
 }
 
 impl ReturnAddress {
+    /// Sends the id of a newly created surface.
     pub fn send(self, value: u64) -> Result<(), ReturnerError> {
         match self {
             ReturnAddress::Memory(s) => s
-                .send(value)
+                .send(Ok(value))
                 .map_err(|_| Report::new(ReturnerError::SenderError)),
+            ReturnAddress::Done(_) => Err(Report::new(ReturnerError::SenderError))
+                .attach_printable("the request was not expected to make a surface"),
+            ReturnAddress::Socket(r) => r.ok(json::json!({ "id": value })),
+        }
+    }
 
-            // FIXME: unimplemented
-            ReturnAddress::File(f) => Err(Report::new(ReturnerError::FileError(f))),
+    /// Answers a request that asked for some information.
+    pub fn value(self, value: &impl Serialize) -> Result<(), ReturnerError> {
+        match self {
+            ReturnAddress::Memory(_) | ReturnAddress::Done(_) => {
+                Err(Report::new(ReturnerError::SenderError))
+                    .attach_printable("requests made in memory can not be answered with a value")
+            }
+            ReturnAddress::Socket(r) => match json::to_value(value) {
+                Ok(value) => r.ok(value),
+                Err(e) => r.error(e),
+            },
+        }
+    }
+
+    /// Reports the outcome of a request that does not produce a value.
+    pub fn finish<C: Context>(self, res: &Result<(), C>) -> Result<(), ReturnerError> {
+        match res {
+            Ok(()) => match self {
+                ReturnAddress::Memory(_) => Err(Report::new(ReturnerError::SenderError))
+                    .attach_printable("the request was expected to make a surface"),
+                ReturnAddress::Done(s) => s
+                    .send(Ok(()))
+                    .map_err(|_| Report::new(ReturnerError::SenderError)),
+                ReturnAddress::Socket(r) => r.ok(json::Value::Null),
+            },
+            Err(e) => self.fail(e),
         }
     }
-}
 
-impl From<RawFd> for ReturnAddress {
-    fn from(value: RawFd) -> Self {
-        Self::File(value)
+    /// Reports that a request could not be completed.
+    pub fn fail(self, err: impl fmt::Display) -> Result<(), ReturnerError> {
+        match self {
+            ReturnAddress::Memory(s) => s
+                .send(Err(err.to_string()))
+                .map_err(|_| Report::new(ReturnerError::SenderError)),
+            ReturnAddress::Done(s) => s
+                .send(Err(err.to_string()))
+                .map_err(|_| Report::new(ReturnerError::SenderError)),
+            ReturnAddress::Socket(r) => r.error(err),
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum ReturnerError {
     SenderError,
-    FileError(RawFd),
 }
 
 impl fmt::Display for ReturnerError {
@@ -80,9 +265,6 @@ impl fmt::Display for ReturnerError {
             Self::SenderError => {
                 f.write_str("Data was either already sent on this channel or consumer hung up")
             }
-            Self::FileError(fd) => {
-                write!(f, "Failed to write data to specified fd: {fd:?}")
-            }
         }
     }
 }
@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+
+/// Changes to make to a window. Anything left out stays as it is.
+///
+/// Sizes and positions are in physical pixels.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WindowProps {
+    pub title: Option<String>,
+    pub size: Option<Size>,
+    pub position: Option<Position>,
+    pub min_size: Option<Size>,
+    pub max_size: Option<Size>,
+    pub decorations: Option<bool>,
+    pub always_on_top: Option<bool>,
+    pub fullscreen: Option<bool>,
+    pub visible: Option<bool>,
+    pub resizable: Option<bool>,
+    /// Red, green, blue and alpha from 0 to 1.
+    pub background: Option<[f64; 4]>,
+    pub fit: Option<Fit>,
+}
+
+/// How an image is sized to its window.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum Fit {
+    /// As large as it fits while keeping its aspect ratio.
+    #[default]
+    Contain,
+    /// Fill the window, ignoring the aspect ratio.
+    Stretch,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl From<Size> for winit::dpi::PhysicalSize<u32> {
+    #[inline]
+    fn from(value: Size) -> Self {
+        Self::new(value.width, value.height)
+    }
+}
+
+impl From<Position> for winit::dpi::PhysicalPosition<i32> {
+    #[inline]
+    fn from(value: Position) -> Self {
+        Self::new(value.x, value.y)
+    }
+}
@@ -0,0 +1,88 @@
+use crate::prelude::*;
+
+use super::{Event, ReturnerError};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+/// A line written back to a socket client.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Response {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<u64>,
+    #[serde(flatten)]
+    pub body: ResponseBody,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseBody {
+    Ok(json::Value),
+    Error(String),
+    /// Not an answer to a request but something a client subscribed to.
+    Event(Event),
+}
+
+impl Response {
+    #[inline]
+    pub fn event(event: Event) -> Self {
+        Self {
+            request_id: None,
+            body: ResponseBody::Event(event),
+        }
+    }
+}
+
+/// Handle for answering one request made over a socket connection.
+///
+/// Every request gets exactly one [`Response`]. If the handle is dropped
+/// without being used an error is sent in its place so clients waiting on a
+/// `request_id` are never left hanging.
+#[derive(Debug)]
+pub struct Reply {
+    request_id: Option<u64>,
+    tx: Option<mpsc::UnboundedSender<Response>>,
+}
+
+impl Reply {
+    pub fn new(request_id: Option<u64>, tx: mpsc::UnboundedSender<Response>) -> Self {
+        Self {
+            request_id,
+            tx: Some(tx),
+        }
+    }
+
+    /// Reports success with the given value.
+    pub fn ok(mut self, value: json::Value) -> Result<(), ReturnerError> {
+        self.send(ResponseBody::Ok(value))
+    }
+
+    /// Reports failure with the given reason.
+    pub fn error(mut self, err: impl fmt::Display) -> Result<(), ReturnerError> {
+        self.send(ResponseBody::Error(err.to_string()))
+    }
+
+    fn send(&mut self, body: ResponseBody) -> Result<(), ReturnerError> {
+        let tx = self
+            .tx
+            .take()
+            .ok_or(Report::new(ReturnerError::SenderError))?;
+
+        let resp = Response {
+            request_id: self.request_id,
+            body,
+        };
+
+        tx.send(resp)
+            .map_err(|_| Report::new(ReturnerError::SenderError))
+    }
+}
+
+impl Drop for Reply {
+    fn drop(&mut self) {
+        if self.tx.is_some() {
+            let _ = self.send(ResponseBody::Error(
+                "request was dropped before it completed".into(),
+            ));
+        }
+    }
+}
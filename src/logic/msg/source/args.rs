@@ -1,98 +1,628 @@
 // implementation
-// have something that stores the uprocessed args
-// and another thing that keeps the state
-// where a window is opened the channel is kept until it yeild and when it does
-// the coresponding image is opened on it. if none of the channels yeild
-// immediatly then just return none
+// by default one window is opened and, once it is up, given every file as its
+// playlist. with `separate` every file gets a window opened for it straight
+// away and is shown once that window exists, in whatever order they come up.
+// an image piped on stdin always gets a window to itself
 
-use std::{collections::VecDeque, env, path::PathBuf, time::Duration};
+use std::ffi::OsString;
+use std::io::{self, IsTerminal, Read};
+use std::os::unix::ffi::OsStringExt;
+use std::path::{Path, PathBuf};
 
-use tokio::sync::oneshot;
+use futures::stream::{FuturesUnordered, StreamExt};
+use tokio::sync::{mpsc, oneshot};
 
-use crate::{logic::msg::Msg, prelude::*};
+use super::{send, EventSendError};
+use crate::{
+    logic::msg::{Fit, Msg, Position, Request, Size, WindowProps},
+    playlist::{self, Sort},
+    prelude::*,
+    terminal::Protocol,
+};
 
 pub struct ArgEventHandler {
-    window_opens: Vec<Msg>,
-    window_draws: VecDeque<(oneshot::Receiver<u64>, PathBuf)>,
+    launch: Launch,
 }
 
 impl ArgEventHandler {
-    pub fn new() -> Self {
-        Self::new_from_list(env::args())
+    pub fn new(launch: Launch) -> Self {
+        Self { launch }
     }
 
-    pub(crate) fn new_from_list(args: impl Iterator<Item = String>) -> Self {
-        let mut window_opens = Vec::new();
-        let mut window_draws = VecDeque::new();
-        for arg in args.skip(1) {
-            let (tx, rx) = oneshot::channel();
-            window_opens.push(Msg::open(tx));
-            window_draws.push_back((rx, arg.into()));
+    pub(crate) fn new_from_list(files: impl IntoIterator<Item = PathBuf>) -> Self {
+        Self::new(Launch {
+            files: files.into_iter().collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Opens one window with every file in its playlist, or a window for
+    /// every file showing each as soon as its window is open.
+    pub async fn run(self, tx: mpsc::Sender<Request>) -> Result<(), EventSendError> {
+        let Launch {
+            files,
+            stdin,
+            props,
+            separate,
+            ..
+        } = self.launch;
+
+        if let Some(data) = stdin {
+            let (otx, orx) = oneshot::channel();
+            let props = Launch::props_for(&props, Path::new("stdin"));
+            send(&tx, Request::open(None, props, otx)).await?;
+
+            match orx.await {
+                Ok(Ok(id)) => {
+                    let req = Request::new(Msg::ShowImageData {
+                        data,
+                        id: id.into(),
+                        placement: None,
+                    });
+                    send(&tx, req).await?;
+                }
+                Ok(Err(e)) => log::warn!("no window to show stdin in: {e}"),
+                Err(_) => log::warn!("window for stdin was never opened"),
+            }
         }
 
-        Self {
-            window_opens,
-            window_draws,
+        if files.is_empty() {
+            return Ok(());
+        }
+
+        if !separate {
+            let (otx, orx) = oneshot::channel();
+            send(&tx, Request::open(None, props, otx)).await?;
+
+            let id = match orx.await {
+                Ok(Ok(id)) => id,
+                Ok(Err(e)) => {
+                    log::warn!("no window to show files in: {e}");
+                    return Ok(());
+                }
+                Err(_) => {
+                    log::warn!("window for files was never opened");
+                    return Ok(());
+                }
+            };
+
+            let req = Request::new(Msg::SetPlaylist {
+                id: id.into(),
+                paths: files,
+                index: 0,
+            });
+            return send(&tx, req).await;
         }
+
+        let mut opening = FuturesUnordered::new();
+
+        for path in files {
+            let (otx, orx) = oneshot::channel();
+            let props = Launch::props_for(&props, &path);
+            send(&tx, Request::open(None, props, otx)).await?;
+            opening.push(async move { (orx.await, path) });
+        }
+
+        while let Some((window, path)) = opening.next().await {
+            let id = match window {
+                Ok(Ok(id)) => id,
+                Ok(Err(e)) => {
+                    log::warn!("no window to show {path:?} in: {e}");
+                    continue;
+                }
+                Err(_) => {
+                    log::warn!("window for {path:?} was never opened");
+                    continue;
+                }
+            };
+
+            let req = Request::new(Msg::ShowImage {
+                path,
+                id: id.into(),
+                placement: None,
+            });
+            send(&tx, req).await?;
+        }
+
+        Ok(())
     }
 }
 
-impl Iterator for ArgEventHandler {
-    type Item = Msg;
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::msg::ReturnAddress;
+    use crate::logic::SurfaceId;
+    use std::time::Duration;
+    use tokio::time::timeout;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if let Some(open) = self.window_opens.pop() {
-            return Some(open);
+    async fn next(rx: &mut mpsc::Receiver<Request>) -> Option<Request> {
+        timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("argument handler stalled")
+    }
+
+    async fn next_open(rx: &mut mpsc::Receiver<Request>) -> ReturnAddress {
+        let req = next(rx).await.expect("expected a window to be opened");
+        assert!(matches!(req.msg, Msg::OpenWindow { .. }));
+        req.resp
+            .expect("open requests need somewhere to send the id")
+    }
+
+    fn separate(files: impl IntoIterator<Item = PathBuf>) -> ArgEventHandler {
+        ArgEventHandler::new(Launch {
+            files: files.into_iter().collect(),
+            separate: true,
+            ..Default::default()
+        })
+    }
+
+    async fn next_show(rx: &mut mpsc::Receiver<Request>) -> (PathBuf, SurfaceId) {
+        let req = next(rx).await.expect("expected an image to be shown");
+        match req.msg {
+            Msg::ShowImage { path, id, .. } => (path, id),
+            msg => panic!("expected ShowImage, got {msg:?}"),
         }
+    }
+
+    #[tokio::test]
+    async fn plays_every_path_in_one_window() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let files = ["a.png", "b.png", "c.png"].map(PathBuf::from);
+        let task = tokio::spawn(ArgEventHandler::new_from_list(files.clone()).run(tx));
 
-        let Some((mut rx, path)) = self.window_draws.pop_front() else {
-            return None;
+        next_open(&mut rx).await.send(4).unwrap();
+        let req = next(&mut rx).await.expect("expected a playlist");
+        match req.msg {
+            Msg::SetPlaylist { id, paths, index } => {
+                assert_eq!(id, SurfaceId::Window(4));
+                assert_eq!(paths, files);
+                assert_eq!(index, 0);
+            }
+            msg => panic!("expected SetPlaylist, got {msg:?}"),
+        }
+
+        task.await.unwrap().unwrap();
+        assert!(next(&mut rx).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn shows_stdin_in_its_own_window() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let launch = Launch {
+            files: vec!["a.png".into()],
+            stdin: Some(vec![1, 2, 3]),
+            ..Default::default()
         };
+        let task = tokio::spawn(ArgEventHandler::new(launch).run(tx));
 
-        log::info!("trying to get id of opened window");
+        next_open(&mut rx).await.send(1).unwrap();
+        let req = next(&mut rx).await.expect("expected stdin to be shown");
+        match req.msg {
+            Msg::ShowImageData { data, id, .. } => {
+                assert_eq!(data, [1, 2, 3]);
+                assert_eq!(id, SurfaceId::Window(1));
+            }
+            msg => panic!("expected ShowImageData, got {msg:?}"),
+        }
+
+        // the files still get their playlist
+        next_open(&mut rx).await.send(2).unwrap();
+        let req = next(&mut rx).await.expect("expected a playlist");
+        assert!(matches!(req.msg, Msg::SetPlaylist { .. }));
 
-        if let Ok(window) = rx.try_recv() {
-            let id = window.into();
-            Some(Msg::ShowImage { path, id })
-        } else {
-            self.window_draws.push_back((rx, path));
-            // std::thread::yield_now();
-            log::info!("Waiting beacuse nothing is ready yet");
-            std::thread::sleep(Duration::from_secs(1));
-            self.next()
+        task.await.unwrap().unwrap();
+        assert!(next(&mut rx).await.is_none());
+    }
+
+    #[test]
+    fn splits_paths_on_separator() {
+        let paths: Vec<PathBuf> = split_paths(b"a.png\0\0dir/b c.png\nx\0", b'\0').collect();
+        assert_eq!(paths, ["a.png", "dir/b c.png\nx"].map(PathBuf::from));
+    }
+
+    #[tokio::test]
+    async fn shows_each_path_once_its_window_opens() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let files = ["a.png", "b.png", "c.png"].map(PathBuf::from);
+        let task = tokio::spawn(separate(files).run(tx));
+
+        let a = next_open(&mut rx).await;
+        let b = next_open(&mut rx).await;
+        let c = next_open(&mut rx).await;
+
+        // windows can come up in any order
+        b.send(2).unwrap();
+        assert_eq!(
+            next_show(&mut rx).await,
+            ("b.png".into(), SurfaceId::Window(2))
+        );
+        c.send(3).unwrap();
+        a.send(1).unwrap();
+        let mut rest = vec![next_show(&mut rx).await, next_show(&mut rx).await];
+        rest.sort_by(|l, r| l.0.cmp(&r.0));
+        assert_eq!(
+            rest,
+            [
+                ("a.png".into(), SurfaceId::Window(1)),
+                ("c.png".into(), SurfaceId::Window(3)),
+            ]
+        );
+
+        task.await.unwrap().unwrap();
+        assert!(next(&mut rx).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn skips_paths_whose_window_did_not_open() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let files = ["a.png", "b.png"].map(PathBuf::from);
+        let task = tokio::spawn(separate(files).run(tx));
+
+        let a = next_open(&mut rx).await;
+        let b = next_open(&mut rx).await;
+
+        a.fail("no more windows").unwrap();
+        b.send(7).unwrap();
+        assert_eq!(
+            next_show(&mut rx).await,
+            ("b.png".into(), SurfaceId::Window(7))
+        );
+
+        task.await.unwrap().unwrap();
+        assert!(next(&mut rx).await.is_none());
+    }
+
+    #[test]
+    fn parses_geometry() {
+        let size = Some(Size {
+            width: 800,
+            height: 600,
+        });
+        assert_eq!(
+            "800x600".parse(),
+            Ok(Geometry {
+                size,
+                position: None
+            })
+        );
+        assert_eq!(
+            "800x600+10-20".parse(),
+            Ok(Geometry {
+                size,
+                position: Some(Position { x: 10, y: -20 }),
+            })
+        );
+        assert_eq!(
+            "+0+0".parse(),
+            Ok(Geometry {
+                size: None,
+                position: Some(Position { x: 0, y: 0 }),
+            })
+        );
+
+        for bad in ["", "800", "800x", "x600", "800x600+10", "+1+2+3", "axb"] {
+            assert!(bad.parse::<Geometry>().is_err(), "{bad:?} parsed");
+        }
+    }
+
+    #[test]
+    fn parses_colors() {
+        assert_eq!(parse_color("#ff0000"), Ok([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_color("00ff0000"), Ok([0.0, 1.0, 0.0, 0.0]));
+        assert_eq!(parse_color("#f00"), Ok([1.0, 0.0, 0.0, 1.0]));
+        assert_eq!(parse_color("0f08"), Ok([0.0, 1.0, 0.0, 136.0 / 255.0]));
+
+        for bad in ["", "#ff", "#ff00000", "#gg0000", "#+f0000", "#ff00é"] {
+            assert!(parse_color(bad).is_err(), "{bad:?} parsed");
         }
     }
 }
 
-// #[cfg(test)]
-// mod test {
-//     use super::*;
-//
-//     #[test]
-//     fn test_args() {
-//         let path1 = String::from("path1");
-//         let args = ["prog_name".into(), path1.clone()].into_iter();
-//         let mut arg_handle = ArgEventHandler::new_from_list(args);
-//
-//         // assert!(match arg_handle.next() {
-//         //     Some(Request::OpenWindow { .. }) => true,
-//         //     _ => false,
-//         // });
-//
-//         // assert!(match arg_handle.next() {
-//         //     Some(Request::ShowImage { path, .. }) => path == PathBuf::from(path1),
-//         //     _ => false,
-//         // });
-//
-//         // beacuse there is no main thread to respond it will always be None
-//         // assert!(arg_handle.next().is_none())
-//     }
-// }
-
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Parser)]
+#[command(version, about)]
 pub struct Args {
-    #[arg(last = true)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Run as the server even when one is already listening.
+    #[arg(long)]
+    pub server: bool,
+
+    /// Keep the server running after its last window closes, until it is
+    /// sent a `Shutdown` request.
+    #[arg(long, requires = "server")]
+    pub persist: bool,
+
+    /// Stay connected to the server after the files have been sent, closing
+    /// the windows once this exits. From a terminal this gives a prompt to
+    /// control the windows with, otherwise the server's events are printed.
+    #[arg(short, long)]
+    pub attach: bool,
+
+    /// Socket to listen on or connect to [default: `$IMVR_SOCKET` or
+    /// `$XDG_RUNTIME_DIR/imvr/<instance>.sock`]
+    #[arg(long, global = true)]
+    pub socket: Option<PathBuf>,
+
+    /// Name of the server to use, letting several run side by side.
+    #[arg(long, global = true, default_value = "default")]
+    pub instance: String,
+
+    /// Title for the windows [default: the file name]
+    #[arg(short, long)]
+    pub title: Option<String>,
+
+    /// Size and position of the windows, as `WxH`, `WxH+X+Y` or `+X+Y`.
+    #[arg(short, long)]
+    pub geometry: Option<Geometry>,
+
+    /// Open the windows fullscreen.
+    #[arg(short, long)]
+    pub fullscreen: bool,
+
+    /// Colour behind the image, as `rgb`, `rgba`, `rrggbb` or `rrggbbaa`
+    /// with an optional leading `#`.
+    #[arg(short, long, value_parser = parse_color)]
+    pub background: Option<[f64; 4]>,
+
+    /// How images are sized to their window.
+    #[arg(long, value_enum)]
+    pub fit: Option<Fit>,
+
+    /// Open a window for every file instead of stepping through them in one.
+    #[arg(short, long)]
+    pub separate: bool,
+
+    /// Also open images in the subdirectories of directories given.
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// The order to show files in.
+    #[arg(long, value_enum, default_value_t)]
+    pub sort: Sort,
+
+    /// Read the paths of images to open from stdin, one per line.
+    #[arg(short = 'i', long)]
+    pub stdin: bool,
+
+    /// Paths read with `--stdin` are separated by NUL instead of newlines.
+    #[arg(short = '0', long, requires = "stdin")]
+    pub null: bool,
+
+    /// Show raw frames from a FIFO, or `-` for stdin, as they arrive.
+    ///
+    /// The stream starts with a line of json such as
+    /// `{"width":640,"height":480,"format":"Rgb8"}` and is followed by the
+    /// frames. The window is run by this process even if a server is already
+    /// running.
+    #[arg(long, value_name = "PATH")]
+    pub stream: Option<PathBuf>,
+
+    /// Show the images in this terminal instead of in windows, using the
+    /// kitty graphics protocol or sixel. `--geometry` is then counted in
+    /// cells.
+    #[arg(short = 'T', long, conflicts_with_all = ["server", "attach", "stream"])]
+    pub terminal: bool,
+
+    /// How images are drawn in a terminal, by `--terminal` or when sent to the
+    /// server's own terminal [default: guessed from `$TERM`]
+    #[arg(long, value_enum, value_name = "PROTOCOL")]
+    pub terminal_protocol: Option<Protocol>,
+
+    /// Dither images drawn in a terminal with only a few colours, as sixel
+    /// does.
+    #[arg(long)]
+    pub dither: bool,
+
+    /// Images to open. Directories are replaced by the images in them and
+    /// `-` is an image piped on stdin.
+    pub files: Vec<PathBuf>,
+}
+
+impl Args {
+    /// What to open on start up.
+    ///
+    /// Reads stdin if asked to and any directories given to find the files in
+    /// them.
+    pub fn launch(&self) -> Result<Launch, LaunchError> {
+        let dash = Path::new("-");
+        let mut files: Vec<PathBuf> = self
+            .files
+            .iter()
+            .filter(|f| f.as_path() != dash)
+            .cloned()
+            .collect();
+        let piped = files.len() != self.files.len();
+        let streamed = self.stream.as_deref() == Some(dash);
+
+        if [piped, self.stdin, streamed].iter().filter(|b| **b).count() > 1 {
+            return Err(Report::new(LaunchError::StdinTwice));
+        }
+        // reading would wait on whatever is typed until ctrl-d
+        if (piped || self.stdin || streamed) && io::stdin().is_terminal() {
+            return Err(Report::new(LaunchError::StdinTerminal));
+        }
+
+        let mut stdin = None;
+        if piped || self.stdin {
+            let mut buf = Vec::new();
+            io::stdin()
+                .lock()
+                .read_to_end(&mut buf)
+                .change_context(LaunchError::Stdin)?;
+
+            if self.stdin {
+                let sep = if self.null { b'\0' } else { b'\n' };
+                files.extend(split_paths(&buf, sep));
+            } else {
+                stdin = Some(buf);
+            }
+        }
+
+        let geometry = self.geometry.unwrap_or_default();
+        Ok(Launch {
+            files: playlist::expand(&files, self.recursive, self.sort),
+            stdin,
+            stream: self.stream.clone(),
+            props: WindowProps {
+                title: self.title.clone(),
+                size: geometry.size,
+                position: geometry.position,
+                fullscreen: self.fullscreen.then_some(true),
+                background: self.background,
+                fit: self.fit,
+                ..Default::default()
+            },
+            separate: self.separate,
+        })
+    }
+}
+
+/// Paths separated by `sep`, leaving out empty ones.
+fn split_paths(buf: &[u8], sep: u8) -> impl Iterator<Item = PathBuf> + '_ {
+    buf.split(move |b| *b == sep)
+        .filter(|p| !p.is_empty())
+        .map(|p| OsString::from_vec(p.to_vec()).into())
+}
+
+#[derive(Debug)]
+pub enum LaunchError {
+    Stdin,
+    StdinTwice,
+    /// Stdin was asked for but nothing is piped into it.
+    StdinTerminal,
+    /// Neither files nor an image were given.
+    Empty,
+}
+
+impl fmt::Display for LaunchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stdin => f.write_str("failed to read stdin"),
+            Self::StdinTwice => f.write_str("stdin can only be read for one thing"),
+            Self::StdinTerminal => f.write_str("stdin is a terminal, pipe something into it"),
+            Self::Empty => f.write_str("nothing to open, give some files or pipe an image in"),
+        }
+    }
+}
+impl Context for LaunchError {}
+
+/// The windows to open on start up, as given on the command line.
+#[derive(Debug, Clone, Default)]
+pub struct Launch {
     pub files: Vec<PathBuf>,
+    /// An encoded image piped on stdin, shown in a window of its own.
+    pub stdin: Option<Vec<u8>>,
+    /// Where to read raw frames from, `-` being stdin.
+    pub stream: Option<PathBuf>,
+    /// Applied to every window that is opened.
+    pub props: WindowProps,
+    /// A window for every file rather than one playlist.
+    pub separate: bool,
+}
+
+impl Launch {
+    /// Whether there is nothing to show.
+    pub fn is_empty(&self) -> bool {
+        self.files.is_empty() && self.stdin.is_none() && self.stream.is_none()
+    }
+
+    /// The properties of the separate window showing `path`, which is named
+    /// after the file unless a title was given.
+    pub fn props_for(props: &WindowProps, path: &Path) -> WindowProps {
+        let mut props = props.clone();
+        if props.title.is_none() {
+            props.title = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        }
+        props
+    }
+}
+
+/// Window size and position in the style of X11's `-geometry`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Geometry {
+    pub size: Option<Size>,
+    pub position: Option<Position>,
+}
+
+impl std::str::FromStr for Geometry {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let bad = || format!("expected `WxH`, `WxH+X+Y` or `+X+Y`, got {s:?}");
+
+        // the position starts at the first sign after the size
+        let split = s.find(['+', '-']).unwrap_or(s.len());
+        let (size, position) = s.split_at(split);
+
+        let size = match size {
+            "" => None,
+            size => {
+                let (w, h) = size.split_once('x').ok_or_else(bad)?;
+                Some(Size {
+                    width: w.parse().map_err(|_| bad())?,
+                    height: h.parse().map_err(|_| bad())?,
+                })
+            }
+        };
+
+        let position = match position {
+            "" => None,
+            position => {
+                // the sign belongs to the number so split before the second one
+                let second = position[1..].find(['+', '-']).ok_or_else(bad)? + 1;
+                let (x, y) = position.split_at(second);
+                Some(Position {
+                    x: x.parse().map_err(|_| bad())?,
+                    y: y.parse().map_err(|_| bad())?,
+                })
+            }
+        };
+
+        if size.is_none() && position.is_none() {
+            return Err(bad());
+        }
+
+        Ok(Geometry { size, position })
+    }
+}
+
+fn parse_color(s: &str) -> std::result::Result<[f64; 4], String> {
+    let hex = s.strip_prefix('#').unwrap_or(s);
+    let bad = || format!("expected `rgb`, `rgba`, `rrggbb` or `rrggbbaa`, got {s:?}");
+    if !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(bad());
+    }
+    // in the short forms one digit stands for two of the same
+    let (digits, scale) = match hex.len() {
+        3 | 4 => (1, 17),
+        6 | 8 => (2, 1),
+        _ => return Err(bad()),
+    };
+
+    // alpha is left opaque when it is not given
+    let mut rgba = [1.0; 4];
+    for (channel, digits) in rgba.iter_mut().zip(hex.as_bytes().chunks(digits)) {
+        let digits = std::str::from_utf8(digits).expect("hex is ascii");
+        let byte = u8::from_str_radix(digits, 16).expect("digits are hex");
+        *channel = f64::from(byte * scale) / 255.0;
+    }
+    Ok(rgba)
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Read ueberzug commands from stdin, showing each identifier in a window.
+    Layer {
+        /// Format the commands are written in.
+        #[arg(short, long, value_enum, default_value_t)]
+        parser: crate::layer::LayerParser,
+
+        /// Accepted for compatibility, imvr already keeps quiet.
+        #[arg(short, long)]
+        silent: bool,
+    },
 }
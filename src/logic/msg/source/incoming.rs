@@ -0,0 +1,193 @@
+//! Reading lines from a socket client along with the file descriptors it
+//! sends as ancillary data.
+
+use std::collections::VecDeque;
+use std::io;
+use std::mem;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+
+use tokio::io::Interest;
+use tokio::net::unix::OwnedReadHalf;
+use tokio::net::UnixStream;
+
+/// How many received descriptors can wait for their line to be read before
+/// new ones are closed.
+const MAX_PENDING_FDS: usize = 16;
+
+/// The read half of a connection.
+///
+/// Descriptors belong to the line the data they were sent with starts in, and
+/// are claimed by its request with [`Incoming::take_fd`]. The kernel never
+/// delivers them later than the first byte they were sent with, so they are
+/// always queued by the time their line is returned. Any the request does not
+/// claim are closed when the next line is read, so a line that fails to parse
+/// can not hand its descriptors to the ones after it.
+pub(super) struct Incoming {
+    stream: OwnedReadHalf,
+    buf: Vec<u8>,
+    /// Descriptors waiting for their line, along with how many bytes had been
+    /// received before the data they came with.
+    fds: VecDeque<(u64, OwnedFd)>,
+    /// The descriptors of the line last returned.
+    line_fds: VecDeque<OwnedFd>,
+    /// How many bytes have been received.
+    received: u64,
+    /// How many bytes have been returned as lines.
+    returned: u64,
+    eof: bool,
+}
+
+impl Incoming {
+    pub fn new(stream: OwnedReadHalf) -> Self {
+        Self {
+            stream,
+            buf: Vec::new(),
+            fds: VecDeque::new(),
+            line_fds: VecDeque::new(),
+            received: 0,
+            returned: 0,
+            eof: false,
+        }
+    }
+
+    /// Reads the next line without its newline. Returns `None` once the client
+    /// stops sending.
+    ///
+    /// Cancel safe, a line that is partly read is kept for the next call.
+    pub async fn next_line(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(i) = self.buf.iter().position(|b| *b == b'\n') {
+                let mut line: Vec<u8> = self.buf.drain(..=i).collect();
+                self.claim(line.len());
+                line.pop();
+                return into_string(line).map(Some);
+            }
+
+            if self.eof {
+                if self.buf.is_empty() {
+                    self.claim(0);
+                    return Ok(None);
+                }
+                let line = mem::take(&mut self.buf);
+                self.claim(line.len());
+                return into_string(line).map(Some);
+            }
+
+            self.fill().await?;
+        }
+    }
+
+    /// Claims the oldest descriptor sent with the line last returned.
+    pub fn take_fd(&mut self) -> Option<OwnedFd> {
+        self.line_fds.pop_front()
+    }
+
+    /// Hands the descriptors of a line `len` bytes long to it, closing those
+    /// of the line before that were not claimed.
+    fn claim(&mut self, len: usize) {
+        self.line_fds.clear();
+        self.returned += len as u64;
+        let ready = self
+            .fds
+            .iter()
+            .take_while(|(at, _)| *at < self.returned)
+            .count();
+        self.line_fds
+            .extend(self.fds.drain(..ready).map(|(_, fd)| fd));
+    }
+
+    /// Reads whatever is available into the buffer.
+    async fn fill(&mut self) -> io::Result<()> {
+        let stream: &UnixStream = self.stream.as_ref();
+        let mut chunk = [0u8; 4096];
+        let at = self.received;
+
+        loop {
+            stream.readable().await?;
+
+            let res = stream.try_io(Interest::READABLE, || {
+                recv_with_fds(stream.as_raw_fd(), &mut chunk, at, &mut self.fds)
+            });
+
+            match res {
+                Ok(0) => {
+                    self.eof = true;
+                    return Ok(());
+                }
+                Ok(n) => {
+                    self.buf.extend_from_slice(&chunk[..n]);
+                    self.received += n as u64;
+                    return Ok(());
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn into_string(line: Vec<u8>) -> io::Result<String> {
+    String::from_utf8(line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+/// Calls `recvmsg` once, queueing any descriptors that came with the data
+/// along with `at`, how far into the stream the data starts.
+fn recv_with_fds(
+    fd: RawFd,
+    buf: &mut [u8],
+    at: u64,
+    fds: &mut VecDeque<(u64, OwnedFd)>,
+) -> io::Result<usize> {
+    // u64s keep the buffer aligned for cmsghdr
+    let mut control = [0u64; 16];
+
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+
+    // # Safety
+    // msghdr is plain data and all zeros is a valid empty value
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(&control) as _;
+
+    // # Safety
+    // every pointer in msg points into buffers that live until the end of
+    // this function
+    let n = unsafe { libc::recvmsg(fd, &mut msg, libc::MSG_CMSG_CLOEXEC) };
+    if n == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // # Safety
+    // the kernel filled in msg_controllen so walking the headers stays within
+    // `control`
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let hdr = &*cmsg;
+            if hdr.cmsg_level == libc::SOL_SOCKET && hdr.cmsg_type == libc::SCM_RIGHTS {
+                let data = libc::CMSG_DATA(cmsg).cast::<RawFd>();
+                let len = hdr.cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+                for i in 0..len / mem::size_of::<RawFd>() {
+                    let fd = OwnedFd::from_raw_fd(data.add(i).read_unaligned());
+                    if fds.len() < MAX_PENDING_FDS {
+                        fds.push_back((at, fd));
+                    } else {
+                        log::warn!("client sent too many file descriptors, closing one");
+                    }
+                }
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    if msg.msg_flags & libc::MSG_CTRUNC != 0 {
+        log::warn!("client sent more file descriptors than fit in one read");
+    }
+
+    Ok(n as usize)
+}
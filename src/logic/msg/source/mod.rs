@@ -1,17 +1,19 @@
 mod args;
+mod incoming;
 mod socket;
-mod stdin;
+mod stream;
 
 use crate::prelude::*;
 
 use self::args::ArgEventHandler;
+pub use self::args::{Args, Command, Launch, LaunchError};
 // use socket::SocketEventHandler;
-// use stdin::StdinEventHandler;
 
 use ext::collections::ArrayVec;
-use tokio::sync::mpsc;
+use std::path::PathBuf;
+use tokio::sync::{broadcast, mpsc};
 
-use super::Msg;
+use super::{Event, Request};
 
 #[derive(Debug)]
 pub enum EventSendError {
@@ -45,51 +47,54 @@ impl fmt::Display for EventSendError {
 impl Context for EventSendError {}
 
 pub struct EventHandler {
-    handle: ArrayVec<tokio::task::JoinHandle<Result<(), EventSendError>>, 2>,
+    handle: ArrayVec<tokio::task::JoinHandle<Result<(), EventSendError>>, 3>,
 }
 
 impl EventHandler {
-    pub fn spawn(tx: mpsc::Sender<Msg>) -> Self {
+    /// Starts reading requests from the command line, the stream asked for
+    /// and the socket if there is one to listen on.
+    pub fn spawn(
+        tx: mpsc::Sender<Request>,
+        launch: Launch,
+        socket: Option<PathBuf>,
+        events: broadcast::Sender<Event>,
+    ) -> Self {
         let mut handle = ArrayVec::new();
 
+        if let Some(source) = launch.stream.clone() {
+            // --- Stream ------
+            let tx = tx.clone();
+            let props = launch.props.clone();
+            let h = tokio::spawn(async move {
+                let res = self::stream::frames(tx, source, props).await;
+
+                non_fatal!(res);
+
+                log::info!("no more stream frames");
+                Ok(())
+            });
+            handle.push(h);
+        }
+
         {
             // --- Args ---------
             let tx = tx.clone();
             let h = tokio::spawn(async move {
-                let mut ret = Option::<Report<EventSendError>>::None;
-                let args = ArgEventHandler::new();
-                for req in args {
-                    match tx.send(req).await {
-                        Ok(_) => {}
-                        Err(e) => {
-                            let e = Report::new(e);
-                            let e = ext::error::Report::attach_printable(
-                                e,
-                                "failed to get argument request",
-                            );
-                            let e = ext::error::Report::change_context(e, EventSendError::Poll);
-                            if let Some(ref mut r) = ret {
-                                r.extend_one(e);
-                            } else {
-                                ret = Some(e);
-                            }
-                        }
-                    }
-                }
+                let res = ArgEventHandler::new(launch).run(tx).await;
+
+                non_fatal!(res);
+
                 log::info!("no more cli argument events");
-                match ret {
-                    Some(e) => Err(e),
-                    None => Ok(()),
-                }
+                Ok(())
             });
             handle.push(h);
         }
 
-        {
+        if let Some(socket) = socket {
             // --- Socket ------
             let tx = tx.clone();
             let h = tokio::spawn(async move {
-                let res = self::socket::events(tx).await;
+                let res = self::socket::events(tx, socket, events).await;
 
                 non_fatal!(res);
 
@@ -99,7 +104,7 @@ impl EventHandler {
             handle.push(h);
         }
 
-        EventHandler { handle}
+        EventHandler { handle }
     }
 
     pub async fn close(&mut self) -> Result<(), EventSendError> {
@@ -115,6 +120,13 @@ impl EventHandler {
     }
 }
 
+async fn send(tx: &mpsc::Sender<Request>, req: Request) -> Result<(), EventSendError> {
+    tx.send(req)
+        .await
+        .attach_printable("request consumer hung up")
+        .change_context(EventSendError::Poll)
+}
+
 impl Drop for EventHandler {
     fn drop(&mut self) {
         block_on(async { self.close().await }).unwrap()
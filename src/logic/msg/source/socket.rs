@@ -1,108 +1,487 @@
 // Module for reading evnets from socket and emitting requests
 
-use crate::{logic::msg::Msg, prelude::*};
-use std::io;
-use tokio::sync::mpsc;
-
-// #[derive(Debug)]
-// pub enum SocketEventError {
-//     SocketClosed,
-//     CantConnect(&'static str),
-//     NoMoreHandle,
-//     JoinError(&'static str),
-// }
-// impl fmt::Display for SocketEventError {
-//     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-//         match self {
-//             Self::SocketClosed => f.write_str("Socket closed."),
-//             Self::CantConnect(path) => write!(f, "unable to connect to unix socket at {path:?}"),
-//             Self::NoMoreHandle => f.write_str("tried to take join handle but it was missing"),
-//             Self::JoinError(_) => f.write_str("failed to join {name} task"),
-//         }
-//     }
-// }
-// impl Context for SocketEventError {}
-
-pub(super) async fn events(tx: mpsc::Sender<Msg>) -> Result<(), super::EventSendError> {
-    const IMVR_PATH: &str = "/tmp/imvr.sock";
-
-    let listener = tokio::net::UnixListener::bind(IMVR_PATH)
-        .attach_printable("could not connect to socket")
-        .change_context(super::EventSendError::Init)?;
+use super::incoming::Incoming;
+use crate::logic::msg::{
+    Event, EventFilter, EventKind, Msg, RawRequest, Reply, Request, Response, ReturnAddress,
+};
+use crate::prelude::*;
+use futures::future::BoxFuture;
+use futures::stream::{FuturesUnordered, StreamExt};
+use std::path::PathBuf;
+use tokio::io::AsyncWriteExt;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::net::UnixStream;
+use tokio::sync::{broadcast, mpsc, oneshot};
 
-                // let res = tx.send(msg).await;
-                // .attach_printable("failed to send request")
-                // .change_context(EventSenderError::PollError);
-                // non_fatal!(res);
-
-    async fn handle(res: io::Result<(tokio::net::UnixStream, tokio::net::unix::SocketAddr)>) {
-        // let end = tx.closed();
-        // let res = listener.accept().await;
-        //
-        // dbg!(b);
-        // let mut buf = [0; 1028];
-        // tokio::io::AsyncReadExt::read(&mut a, &mut buf);
-        dbg!(&res);
-        res.unwrap();
-
-        // let mut handles = Vec::new();
-        //
-        // let mut inner = || {
-        //     // listener.set_nonblocking(true)?;
-        //     for res in listener.incoming() {
-        //         let mut stream = res
-        //             .attach_printable("Socket connection closed")
-        //             .change_context(SocketEventError::SocketClosed)?;
-        //
-        //         let h = tokio::spawn(async move {
-        //             // read the stream line by line and use serde to parse it as json
-        //             // then send the event to the listener where it can be parsed to
-        //             // a request
-        //             let _ = stream.read(&mut []);
-        //             // let _ = stream.write(b"hello world");
-        //             let _ = stream.write(b"");
-        //         });
-        //         handles.push(h);
-        //     }
-        //
-        //     unreachable!("socket listener failed to wait for new connections")
-        // };
-        //
-        // let res: Result<Infallible, SocketEventError> = inner();
-        // let e = res.unwrap_err();
-        // for handle in handles {
-        //     if !handle.is_finished() {
-        //         let _ = handle.await.unwrap_err();
-        //         // e.extend_one(a)
-        //     }
-        // }
-        //
-        // return e;
-
-        // close
-        // let h = self
-        //     .handle
-        //     .take()
-        //     .ok_or(Report::new(SocketEventError::NoMoreHandle))?;
-        // h.abort();
-        // let e = h
-        //     .await
-        //     .attach_printable("failed to join thread for reasons.")
-        //     .change_context(SocketEventError::JoinError("spawner"))?;
-        //
-        // log::error!("socket threw error (as expected): {e}");
-        //
-        // Ok(())
+#[derive(Debug)]
+pub enum SocketEventError {
+    Read,
+    Write,
+    Hangup,
+}
+impl fmt::Display for SocketEventError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Read => f.write_str("failed to read from client"),
+            Self::Write => f.write_str("failed to write to client"),
+            Self::Hangup => f.write_str("request consumer hung up"),
+        }
     }
+}
+impl Context for SocketEventError {}
+
+pub(super) async fn events(
+    tx: mpsc::Sender<Request>,
+    path: PathBuf,
+    events: broadcast::Sender<Event>,
+) -> Result<(), super::EventSendError> {
+    let listener = tokio::net::UnixListener::bind(&path)
+        .attach_printable_lazy(|| format!("could not listen on socket {path:?}"))
+        .change_context(super::EventSendError::Init)?;
 
     loop {
         tokio::select! {
             _ = tx.closed() => {
                 break
             }
-            r = listener.accept() => handle(r).await,
+            r = listener.accept() => match r {
+                Ok((stream, _)) => {
+                    let conn = Connection::new(tx.clone(), events.clone());
+                    tokio::spawn(async move {
+                        if let Err(e) = conn.run(stream).await {
+                            log::warn!("socket connection ended with error: \n\r{e:?}");
+                        }
+                    });
+                }
+                Err(e) => log::warn!("failed to accept socket connection: {e}"),
+            },
         }
     }
 
     Ok(())
 }
+
+/// State kept for each connected client.
+///
+/// Reads newline delimited json [`RawRequest`]s until the client hangs up and
+/// writes a [`Response`] line back for each of them, along with any events the
+/// client subscribed to.
+struct Connection {
+    tx: mpsc::Sender<Request>,
+    events: broadcast::Sender<Event>,
+
+    /// Handed to every [`Reply`]. Dropped once the client stops sending so the
+    /// reciving end closes after the last outstanding reply is written.
+    replies: Option<mpsc::UnboundedSender<Response>>,
+
+    subscription: Option<Subscription>,
+
+    /// Windows this client opened that are closed when it hangs up.
+    owned: Vec<u64>,
+
+    /// Every event, watched for owned windows closing so they are not closed
+    /// a second time.
+    closed: broadcast::Receiver<Event>,
+
+    /// Owned windows that have been asked for but not created yet.
+    opening: FuturesUnordered<PendingOpen>,
+}
+
+/// Resolves to the id of a window once it opens (or why it could not be),
+/// along with the reply to send the id on.
+type PendingOpen = BoxFuture<'static, (std::result::Result<u64, String>, Reply)>;
+
+impl Connection {
+    fn new(tx: mpsc::Sender<Request>, events: broadcast::Sender<Event>) -> Self {
+        Self {
+            tx,
+            closed: events.subscribe(),
+            events,
+            replies: None,
+            subscription: None,
+            owned: Vec::new(),
+            opening: FuturesUnordered::new(),
+        }
+    }
+
+    async fn run(mut self, stream: UnixStream) -> Result<(), SocketEventError> {
+        let res = self.serve(stream).await;
+
+        // the client may have left without us seeing an eof
+        let _ = self.close_owned().await;
+
+        res
+    }
+
+    async fn serve(&mut self, stream: UnixStream) -> Result<(), SocketEventError> {
+        let (read, mut write) = stream.into_split();
+        let mut incoming = Incoming::new(read);
+
+        let (resp_tx, mut resp_rx) = mpsc::unbounded_channel();
+        self.replies = Some(resp_tx);
+        let mut answered = false;
+
+        loop {
+            tokio::select! {
+                line = incoming.next_line(), if self.replies.is_some() => {
+                    match line.change_context(SocketEventError::Read)? {
+                        Some(line) => self.request(&line, &mut incoming).await?,
+                        None => {
+                            // nobody is left to send events to either
+                            self.replies = None;
+                            self.subscription = None;
+                            self.close_owned().await?;
+                        }
+                    }
+                }
+                Some((res, reply)) = self.opening.next(), if !self.opening.is_empty() => {
+                    match res {
+                        Ok(id) => {
+                            let _ = ReturnAddress::Socket(reply).send(id);
+                            self.owned.push(id);
+                            if self.replies.is_none() {
+                                // the client hung up while this was opening
+                                self.close_owned().await?;
+                            }
+                        }
+                        Err(e) => {
+                            let _ = reply.error(e);
+                        }
+                    }
+                }
+                resp = resp_rx.recv(), if !answered => match resp {
+                    Some(resp) => write_response(&mut write, &resp).await?,
+                    None => answered = true,
+                },
+                event = self.closed.recv(), if !self.owned.is_empty() => {
+                    if let Ok(event) = event {
+                        self.forget(&event);
+                    }
+                }
+                event = next_event(&mut self.subscription), if self.subscription.is_some() => {
+                    match event {
+                        Some(event) => write_response(&mut write, &Response::event(event)).await?,
+                        None => self.subscription = None,
+                    }
+                }
+                // the client is done sending, has all its answers and is not
+                // waiting on events
+                else => break,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parses a line from the client and acts on it.
+    ///
+    /// Lines that fail to parse are answered with an error rather than ending
+    /// the connection.
+    async fn request(
+        &mut self,
+        line: &str,
+        incoming: &mut Incoming,
+    ) -> Result<(), SocketEventError> {
+        let Some(replies) = &self.replies else {
+            return Ok(());
+        };
+
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+
+        let raw = match json::from_str::<RawRequest>(line) {
+            Ok(raw) => raw,
+            Err(e) => {
+                log::warn!("malformed socket request: {e}");
+                let _ = Reply::new(None, replies.clone()).error(e);
+                return Ok(());
+            }
+        };
+
+        let reply = Reply::new(raw.request_id, replies.clone());
+
+        let mut msg = raw.msg;
+        if let Msg::ShowImageFd { fd, .. } = &mut msg {
+            *fd = incoming.take_fd();
+        }
+
+        match msg {
+            Msg::Subscribe { events } => {
+                self.subscription = Some(Subscription {
+                    rx: self.events.subscribe(),
+                    filter: events,
+                });
+                let _ = reply.ok(json::Value::Null);
+            }
+            Msg::OpenWindow {
+                detached: false,
+                name,
+                props,
+            } => {
+                // wait on the id here so the window can be closed with us
+                let (tx, rx) = oneshot::channel();
+                let req = Request {
+                    msg: Msg::OpenWindow {
+                        detached: false,
+                        name,
+                        props,
+                    },
+                    resp: Some(ReturnAddress::Memory(tx)),
+                };
+                self.tx
+                    .send(req)
+                    .await
+                    .map_err(|_| Report::new(SocketEventError::Hangup))?;
+                self.opening.push(Box::pin(async move {
+                    let res = rx
+                        .await
+                        .unwrap_or_else(|_| Err("window could not be opened".into()));
+                    (res, reply)
+                }));
+            }
+            msg => {
+                let req = Request {
+                    msg,
+                    resp: Some(ReturnAddress::Socket(reply)),
+                };
+                self.tx
+                    .send(req)
+                    .await
+                    .map_err(|_| Report::new(SocketEventError::Hangup))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Stops owning a window once it has closed.
+    fn forget(&mut self, event: &Event) {
+        if let EventKind::Closed = event.kind {
+            self.owned.retain(|&id| id != event.window);
+        }
+    }
+
+    /// Closes every window this client owns.
+    async fn close_owned(&mut self) -> Result<(), SocketEventError> {
+        // catch up on windows that closed while the client was hanging up
+        while let Some(event) = next_pending(&mut self.closed) {
+            self.forget(&event);
+        }
+
+        for id in std::mem::take(&mut self.owned) {
+            log::info!("client hung up, closing window {id}");
+            let req = Request::new(Msg::CloseWindow { id: id.into() });
+            self.tx
+                .send(req)
+                .await
+                .map_err(|_| Report::new(SocketEventError::Hangup))?;
+        }
+        Ok(())
+    }
+}
+
+/// The events a client asked to be told about.
+struct Subscription {
+    rx: broadcast::Receiver<Event>,
+    /// The kinds of events to send, all of them if empty.
+    filter: Vec<EventFilter>,
+}
+
+impl Subscription {
+    /// Waits for the next event the client wants. Returns `None` once no more
+    /// events can come.
+    async fn next(&mut self) -> Option<Event> {
+        use tokio::sync::broadcast::error::RecvError;
+        loop {
+            match self.rx.recv().await {
+                Ok(event) if self.wants(&event) => return Some(event),
+                Ok(_) => {}
+                Err(RecvError::Lagged(n)) => {
+                    log::warn!("subscriber fell behind, dropped {n} events")
+                }
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    }
+
+    fn wants(&self, event: &Event) -> bool {
+        self.filter.is_empty() || self.filter.contains(&event.kind.filter())
+    }
+}
+
+async fn next_event(sub: &mut Option<Subscription>) -> Option<Event> {
+    sub.as_mut()?.next().await
+}
+
+/// Takes an event that has already been sent without waiting for one,
+/// skipping over any that were missed.
+fn next_pending(rx: &mut broadcast::Receiver<Event>) -> Option<Event> {
+    use tokio::sync::broadcast::error::TryRecvError;
+    loop {
+        match rx.try_recv() {
+            Ok(event) => return Some(event),
+            Err(TryRecvError::Lagged(_)) => {}
+            Err(TryRecvError::Empty | TryRecvError::Closed) => return None,
+        }
+    }
+}
+
+async fn write_response(
+    write: &mut OwnedWriteHalf,
+    resp: &Response,
+) -> Result<(), SocketEventError> {
+    let mut line = json::to_string(resp).change_context(SocketEventError::Write)?;
+    line.push('\n');
+    write
+        .write_all(line.as_bytes())
+        .await
+        .change_context(SocketEventError::Write)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::os::fd::{FromRawFd, OwnedFd};
+    use std::time::Duration;
+    use tokio::io::{AsyncBufReadExt, BufReader};
+    use tokio::time::timeout;
+
+    const PROMPTLY: Duration = Duration::from_secs(1);
+
+    #[tokio::test]
+    async fn subscribers_that_hang_up_are_let_go() {
+        let (tx, _rx) = mpsc::channel(4);
+        let (events, _) = broadcast::channel(4);
+        let (client, server) = UnixStream::pair().unwrap();
+        let task = tokio::spawn(Connection::new(tx, events.clone()).run(server));
+
+        let (read, mut write) = client.into_split();
+        write.write_all(b"{\"Subscribe\":{}}\n").await.unwrap();
+        let mut lines = BufReader::new(read).lines();
+        assert_eq!(
+            lines.next_line().await.unwrap().as_deref(),
+            Some(r#"{"ok":null}"#)
+        );
+        drop(write);
+
+        // events could still be sent, it is the client that is gone
+        let res = timeout(PROMPTLY, task).await;
+        assert!(matches!(res, Ok(Ok(Ok(())))));
+        drop(events);
+    }
+
+    #[tokio::test]
+    async fn windows_that_closed_are_not_closed_again() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (events, _) = broadcast::channel(4);
+        let (client, server) = UnixStream::pair().unwrap();
+        let task = tokio::spawn(Connection::new(tx, events.clone()).run(server));
+
+        let (read, mut write) = client.into_split();
+        let mut lines = BufReader::new(read).lines();
+        for id in [3, 4] {
+            write.write_all(b"{\"OpenWindow\":{}}\n").await.unwrap();
+            let req = timeout(PROMPTLY, rx.recv()).await.unwrap().unwrap();
+            let Some(ReturnAddress::Memory(resp)) = req.resp else {
+                panic!("expected to be told the id");
+            };
+            resp.send(Ok(id)).unwrap();
+            let line = lines.next_line().await.unwrap().unwrap();
+            assert_eq!(line, format!(r#"{{"ok":{{"id":{id}}}}}"#));
+        }
+
+        events
+            .send(Event {
+                window: 3,
+                kind: EventKind::Closed,
+            })
+            .unwrap();
+        drop(write);
+
+        let res = timeout(PROMPTLY, task).await;
+        assert!(matches!(res, Ok(Ok(Ok(())))));
+        let req = rx.recv().await.expect("expected the open window to close");
+        assert!(matches!(
+            req.msg,
+            Msg::CloseWindow {
+                id: crate::logic::SurfaceId::Window(4)
+            }
+        ));
+        assert!(rx.recv().await.is_none());
+    }
+    /// Sends `line` with `fd` attached, the way clients share images.
+    fn send_with_fd(socket: &std::os::unix::net::UnixStream, line: &[u8], fd: &OwnedFd) {
+        use std::os::fd::{AsRawFd, RawFd};
+
+        let mut iov = libc::iovec {
+            iov_base: line.as_ptr() as *mut _,
+            iov_len: line.len(),
+        };
+        let mut control = [0u64; 4];
+        let fd_len = std::mem::size_of::<RawFd>() as u32;
+
+        // # Safety
+        // msghdr is plain data and every pointer in it outlives the sendmsg
+        unsafe {
+            let mut msg: libc::msghdr = std::mem::zeroed();
+            msg.msg_iov = &mut iov;
+            msg.msg_iovlen = 1;
+            msg.msg_control = control.as_mut_ptr().cast();
+            msg.msg_controllen = libc::CMSG_SPACE(fd_len) as _;
+
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = libc::SOL_SOCKET;
+            (*cmsg).cmsg_type = libc::SCM_RIGHTS;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(fd_len) as _;
+            libc::CMSG_DATA(cmsg)
+                .cast::<RawFd>()
+                .write_unaligned(fd.as_raw_fd());
+
+            let sent = libc::sendmsg(socket.as_raw_fd(), &msg, 0);
+            assert_eq!(sent, line.len() as isize);
+        }
+    }
+
+    fn inode(fd: &OwnedFd) -> u64 {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::File::from(fd.try_clone().unwrap())
+            .metadata()
+            .unwrap()
+            .ino()
+    }
+
+    #[tokio::test]
+    async fn malformed_lines_do_not_pass_on_their_descriptors() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (events, _) = broadcast::channel(4);
+        let (client, server) = std::os::unix::net::UnixStream::pair().unwrap();
+        server.set_nonblocking(true).unwrap();
+        let server = UnixStream::from_std(server).unwrap();
+        let task = tokio::spawn(Connection::new(tx, events.clone()).run(server));
+
+        let memfd = || {
+            let fd = unsafe { libc::memfd_create(c"image".as_ptr(), 0) };
+            assert!(fd >= 0);
+            unsafe { OwnedFd::from_raw_fd(fd) }
+        };
+        let (stray, shared) = (memfd(), memfd());
+
+        let malformed = br#"{"ShowImageFd":{"width":"wide"}}"#;
+        let valid = br#"{"ShowImageFd":{"width":1,"height":1,"format":"Rgb8","id":{"Window":1}}}"#;
+        send_with_fd(&client, &[&malformed[..], b"\n"].concat(), &stray);
+        send_with_fd(&client, &[&valid[..], b"\n"].concat(), &shared);
+
+        let req = timeout(PROMPTLY, rx.recv()).await.unwrap().unwrap();
+        let Msg::ShowImageFd { fd: Some(fd), .. } = req.msg else {
+            panic!("expected the image to come with a descriptor");
+        };
+        assert_eq!(inode(&fd), inode(&shared));
+
+        drop(req.resp);
+        drop(client);
+        let res = timeout(PROMPTLY, task).await;
+        assert!(matches!(res, Ok(Ok(Ok(())))));
+        drop(events);
+    }
+}
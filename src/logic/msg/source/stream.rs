@@ -0,0 +1,329 @@
+//! Reading a live sequence of raw frames from stdin or a FIFO.
+//!
+//! A stream starts with one line of json describing the frames:
+//!
+//! ```json
+//! {"width":640,"height":480,"format":"Rgb8","stride":1920}
+//! ```
+//!
+//! `stride` is optional and defaults to tightly packed rows. Every frame after
+//! it is exactly `stride * height` bytes, at most [`MAX_FRAME`]. Frames are
+//! shown in a window of their own and when the window can not keep up the
+//! frames it missed are dropped so it always shows the newest one.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde::Deserialize;
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::sync::{mpsc, oneshot, Notify};
+
+use super::{send, EventSendError, Launch};
+use crate::logic::msg::window::layout;
+use crate::logic::msg::{Msg, Request, ReturnAddress, WindowProps};
+use crate::prelude::*;
+use crate::render::gpu::image::{ImageInfo, PixelFormat};
+
+/// The most bytes a frame can take, with room for an 8K frame with alpha.
+const MAX_FRAME: u64 = 1 << 28;
+
+/// The first line of a stream.
+#[derive(Debug, Deserialize)]
+struct Header {
+    width: u32,
+    height: u32,
+    format: PixelFormat,
+    #[serde(default)]
+    stride: Option<u32>,
+}
+
+impl Header {
+    /// How the frames are laid out, failing for frames that can not be laid
+    /// out and ones larger than [`MAX_FRAME`].
+    fn info(&self) -> std::result::Result<ImageInfo, String> {
+        let info = layout(self.format, self.width, self.height, self.stride)?;
+        let len = u64::from(info.stride.y) * u64::from(info.size.y);
+        if len > MAX_FRAME {
+            return Err(format!(
+                "frames of {len} bytes are larger than the {MAX_FRAME} allowed"
+            ));
+        }
+        Ok(info)
+    }
+}
+
+/// Shows the frames read from `source` (`-` for stdin) until it ends or the
+/// window showing them is closed.
+pub(super) async fn frames(
+    tx: mpsc::Sender<Request>,
+    source: PathBuf,
+    props: WindowProps,
+) -> Result<(), EventSendError> {
+    let reader: Box<dyn AsyncRead + Unpin + Send> = if source == Path::new("-") {
+        Box::new(tokio::io::stdin())
+    } else {
+        let file = tokio::fs::File::open(&source)
+            .await
+            .attach_printable_lazy(|| format!("could not open stream {source:?}"))
+            .change_context(EventSendError::Init)?;
+        Box::new(file)
+    };
+    let mut reader = BufReader::new(reader);
+
+    let mut line = String::new();
+    reader
+        .read_line(&mut line)
+        .await
+        .attach_printable("could not read stream header")
+        .change_context(EventSendError::Init)?;
+    let header = json::from_str::<Header>(&line)
+        .attach_printable_lazy(|| format!("malformed stream header {line:?}"))
+        .change_context(EventSendError::Init)?;
+    let info = header
+        .info()
+        .map_err(|e| Report::new(EventSendError::Init).attach_printable(e))?;
+
+    let name = match source == Path::new("-") {
+        true => Path::new("stdin"),
+        false => &source,
+    };
+    let (otx, orx) = oneshot::channel();
+    let props = Launch::props_for(&props, name);
+    send(&tx, Request::open(None, props, otx)).await?;
+    let id = match orx.await {
+        Ok(Ok(id)) => id,
+        Ok(Err(e)) => {
+            log::warn!("no window to stream {source:?} to: {e}");
+            return Ok(());
+        }
+        Err(_) => {
+            log::warn!("window for stream {source:?} was never opened");
+            return Ok(());
+        }
+    };
+
+    let mailbox = Mailbox::default();
+    let read = async {
+        let res = read_frames(&mut reader, info.byte_len(), &mailbox).await;
+        mailbox.close();
+        res
+    };
+    let show = show_frames(&tx, id, info, &mailbox);
+    tokio::pin!(read, show);
+
+    let mut reading = true;
+    loop {
+        tokio::select! {
+            _ = tx.closed() => return Ok(()),
+            res = &mut read, if reading => {
+                reading = false;
+                if let Err(e) = res {
+                    log::warn!("stream {source:?} ended early: {e}");
+                }
+            }
+            // once the window is gone nothing else is read, even if the
+            // other end keeps writing
+            res = &mut show => return res,
+        }
+    }
+}
+
+/// Reads whole frames into the mailbox until the stream ends.
+async fn read_frames(
+    reader: &mut (impl AsyncRead + Unpin),
+    len: usize,
+    mailbox: &Mailbox<Vec<u8>>,
+) -> std::io::Result<()> {
+    loop {
+        let mut frame = vec![0; len];
+        let mut filled = 0;
+        while filled < len {
+            match reader.read(&mut frame[filled..]).await? {
+                0 if filled == 0 => return Ok(()),
+                0 => return Err(std::io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+
+        if mailbox.put(frame) {
+            log::debug!("dropped a frame the window did not get to");
+        }
+    }
+}
+
+/// Sends the newest frame to the window each time it is done with the last.
+async fn show_frames(
+    tx: &mpsc::Sender<Request>,
+    id: u64,
+    info: ImageInfo,
+    mailbox: &Mailbox<Vec<u8>>,
+) -> Result<(), EventSendError> {
+    while let Some(data) = mailbox.take().await {
+        let (otx, orx) = oneshot::channel();
+        let req = Request {
+            msg: Msg::ShowImageRaw {
+                width: info.size.x,
+                height: info.size.y,
+                format: info.pixel_format,
+                stride: Some(info.stride.y),
+                data,
+                id: id.into(),
+                placement: None,
+            },
+            resp: Some(ReturnAddress::Done(otx)),
+        };
+        send(tx, req).await?;
+
+        match orx.await {
+            Ok(Ok(())) => {}
+            Ok(Err(e)) => {
+                log::info!("stopping stream: {e}");
+                return Ok(());
+            }
+            Err(_) => return Ok(()),
+        }
+    }
+    Ok(())
+}
+
+/// Holds the newest value until it is taken, replacing any older one that
+/// has not been.
+struct Mailbox<T> {
+    slot: Mutex<Slot<T>>,
+    notify: Notify,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    closed: bool,
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self {
+            slot: Mutex::new(Slot {
+                value: None,
+                closed: false,
+            }),
+            notify: Notify::new(),
+        }
+    }
+}
+
+impl<T> Mailbox<T> {
+    /// Leaves `value` to be taken. Returns whether an older value was
+    /// dropped for it.
+    fn put(&self, value: T) -> bool {
+        let stale = self.slot.lock().unwrap().value.replace(value).is_some();
+        self.notify.notify_one();
+        stale
+    }
+
+    /// Lets [`Mailbox::take`] return `None` once the last value is taken.
+    fn close(&self) {
+        self.slot.lock().unwrap().closed = true;
+        self.notify.notify_one();
+    }
+
+    /// Waits for a value, returning `None` if there will be no more.
+    async fn take(&self) -> Option<T> {
+        loop {
+            {
+                let mut slot = self.slot.lock().unwrap();
+                if let Some(value) = slot.value.take() {
+                    return Some(value);
+                }
+                if slot.closed {
+                    return None;
+                }
+            }
+            // a put between the check and here leaves a permit so this
+            // does not miss it
+            self.notify.notified().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::msg::ReturnerError;
+    use std::time::Duration;
+    use tokio::io::AsyncWriteExt;
+    use tokio::time::timeout;
+
+    #[tokio::test]
+    async fn mailbox_keeps_only_the_newest() {
+        let mailbox = Mailbox::default();
+        assert!(!mailbox.put(1));
+        assert!(mailbox.put(2));
+        assert!(mailbox.put(3));
+        assert_eq!(mailbox.take().await, Some(3));
+
+        mailbox.put(4);
+        mailbox.close();
+        assert_eq!(mailbox.take().await, Some(4));
+        assert_eq!(mailbox.take().await, None);
+    }
+
+    async fn next_frame(rx: &mut mpsc::Receiver<Request>) -> (Vec<u8>, ReturnAddress) {
+        let req = timeout(Duration::from_secs(1), rx.recv())
+            .await
+            .expect("stream stalled")
+            .expect("expected a frame");
+        match req.msg {
+            Msg::ShowImageRaw { data, .. } => (data, req.resp.unwrap()),
+            msg => panic!("expected ShowImageRaw, got {msg:?}"),
+        }
+    }
+
+    #[tokio::test]
+    async fn frames_are_dropped_while_the_window_is_busy() {
+        let (mut writer, mut reader) = tokio::io::duplex(64);
+        let (tx, mut rx) = mpsc::channel(4);
+        let (done, read_all) = oneshot::channel();
+        let info = ImageInfo::new(PixelFormat::Mono8, 2, 1).unwrap();
+
+        let task = tokio::spawn(async move {
+            let mailbox = Mailbox::default();
+            let read = async {
+                let res = read_frames(&mut reader, info.byte_len(), &mailbox).await;
+                mailbox.close();
+                let _ = done.send(());
+                res
+            };
+            let (read, show) = tokio::join!(read, show_frames(&tx, 1, info, &mailbox));
+            read.unwrap();
+            show
+        });
+
+        writer.write_all(&[1, 1]).await.unwrap();
+        let (first, resp) = next_frame(&mut rx).await;
+        assert_eq!(first, [1, 1]);
+
+        // more frames arrive before the window is done with the first
+        writer.write_all(&[2, 2, 3, 3]).await.unwrap();
+        writer.shutdown().await.unwrap();
+        // once everything is read only the newest frame is left waiting
+        read_all.await.unwrap();
+        resp.finish::<ReturnerError>(&Ok(())).unwrap();
+
+        let (last, resp) = next_frame(&mut rx).await;
+        assert_eq!(last, [3, 3]);
+        resp.finish::<ReturnerError>(&Ok(())).unwrap();
+
+        task.await.unwrap().unwrap();
+        assert!(rx.recv().await.is_none());
+    }
+
+    #[test]
+    fn rejects_frames_that_can_not_be_laid_out() {
+        let header = |line: &str| json::from_str::<Header>(line).unwrap().info();
+
+        assert!(header(r#"{"width":2,"height":1,"format":"Rgb8"}"#).is_ok());
+        assert!(header(r#"{"width":0,"height":1,"format":"Rgb8"}"#).is_err());
+        assert!(header(r#"{"width":2,"height":1,"format":"Rgb8","stride":5}"#).is_err());
+        assert!(header(r#"{"width":4294967295,"height":1,"format":"Rgb8"}"#).is_err());
+        assert!(header(r#"{"width":65536,"height":65536,"format":"Mono8"}"#).is_err());
+    }
+}
@@ -1,40 +1,58 @@
-// use crate::prelude::*;
-//
-// use crossterm::event::Event as CrossEvent;
-
-// use super::{FocusChange, WindowType};
-
-use super::Msg;
+use super::{Msg, Request, ReturnAddress};
+use crate::logic::SurfaceId;
+use crate::terminal::Placement;
+use crate::util::RawImage;
 
+/// A request for the terminal imvr was started in rather than a window.
 #[derive(Debug)]
 pub enum TerminalMsg {
-    None,
+    /// Shows the image at `place`, or reports why it could not be loaded.
+    ShowImage {
+        image: Result<RawImage, String>,
+        place: Placement,
+        resp: Option<ReturnAddress>,
+    },
+    /// Removes every image shown in the terminal.
+    Clear { resp: Option<ReturnAddress> },
 }
 
-impl Msg {
+impl Request {
+    /// Takes the request if it is for the terminal, loading its image if it
+    /// has one. A request for the terminal is always taken, even if its image
+    /// fails to load, so it never ends up at a window.
     pub fn as_terminal(&mut self) -> Option<TerminalMsg> {
-        Some(TerminalMsg::None)
+        use SurfaceId::Terminal;
+
+        let place = match &self.msg {
+            Msg::CloseWindow { id: Terminal } => {
+                let resp = self.resp.take();
+                return Some(TerminalMsg::Clear { resp });
+            }
+            Msg::ShowImage {
+                id: Terminal,
+                placement,
+                ..
+            }
+            | Msg::ShowImageData {
+                id: Terminal,
+                placement,
+                ..
+            }
+            | Msg::ShowImageRaw {
+                id: Terminal,
+                placement,
+                ..
+            }
+            | Msg::ShowImageFd {
+                id: Terminal,
+                placement,
+                ..
+            } => placement.unwrap_or_default(),
+            _ => return None,
+        };
+
+        let (_, image) = self.msg.load_image()?;
+        let resp = self.resp.take();
+        Some(TerminalMsg::ShowImage { image, place, resp })
     }
 }
-
-// impl From<CrossEvent> for Event {
-//     fn from(value: CrossEvent) -> Self {
-//         match value {
-//             CrossEvent::FocusGained => Event::Focus {
-//                 change: FocusChange::Gained,
-//                 window: WindowType::Terminal,
-//             },
-//             CrossEvent::FocusLost => Event::Focus {
-//                 change: FocusChange::Lost,
-//                 window: WindowType::Terminal,
-//             },
-//             CrossEvent::Key(k) => Event::Input {
-//                 window: WindowType::Terminal,
-//                 input: k.some_into().unwrap(),
-//             },
-//             CrossEvent::Mouse(_) => todo!(),
-//             CrossEvent::Paste(_) => todo!(),
-//             CrossEvent::Resize(_, _) => todo!(),
-//         }
-//     }
-// }
@@ -1,10 +1,19 @@
 use crate::prelude::*;
 
 use super::key::Key;
-use super::{Msg, ReturnAddress};
+use super::{Msg, Request, ReturnAddress, WindowProps};
+use crate::logic::SurfaceId;
+use crate::playlist::{Navigation, Playlist};
+use crate::render::gpu::image::{ImageInfo, PixelFormat};
 use crate::util::RawImage;
 use ext::glam::UVec2;
 use ext::parse::MoveIt;
+use memmap2::MmapOptions;
+use std::fs::File;
+use std::mem;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::PathBuf;
+use winit::keyboard::ModifiersState;
 use winit::window::WindowId;
 
 /// A message that closely resemblems the final Requested.
@@ -13,33 +22,268 @@ use winit::window::WindowId;
 #[derive(Debug)]
 pub enum WindowMsg {
     Many(Vec<WindowMsg>),
-    ShowImage { image: RawImage, id: WindowId },
-    OpenWindow { resp: ReturnAddress },
-    CloseWindow { id: WindowId },
+    ShowImage { image: RawImage, path: Option<PathBuf>, id: SurfaceId, resp: Option<ReturnAddress> },
+    OpenWindow { name: Option<String>, props: WindowProps, resp: Option<ReturnAddress> },
+    CloseWindow { id: SurfaceId, resp: Option<ReturnAddress> },
+    SetPlaylist { id: SurfaceId, playlist: Playlist, resp: Option<ReturnAddress> },
+    Navigate { id: SurfaceId, to: Navigation, resp: Option<ReturnAddress> },
+    /// Navigation asked for from within a window.
+    NavigateWindow { id: WindowId, to: Navigation },
+    Zoom { id: SurfaceId, factor: f32, relative: bool, resp: Option<ReturnAddress> },
+    Rotate { id: SurfaceId, degrees: f32, resp: Option<ReturnAddress> },
+    SetWindowProps { id: SurfaceId, props: WindowProps, resp: Option<ReturnAddress> },
+    ListWindows { resp: Option<ReturnAddress> },
+    GetWindow { id: SurfaceId, resp: Option<ReturnAddress> },
+    /// The user or the platform wants a window gone.
+    CloseRequested { id: WindowId },
     Resize { size: UVec2, id: WindowId },
     WindowRedraw { id: WindowId },
+    Shutdown { resp: Option<ReturnAddress> },
     Exit,
 }
 
-impl Msg {
+impl Request {
     pub fn as_window(&mut self) -> Option<WindowMsg> {
-        match self {
-            Msg::ShowImage { path, id } => {
-                let id = id.as_id()?.into();
-                let image = image::open(path).unwrap();
-                let image = image.into();
-                Some(WindowMsg::ShowImage { image, id })
+        if let Some((id, image)) = self.msg.load_image() {
+            return match image {
+                Ok(image) => {
+                    let path = match &self.msg {
+                        Msg::ShowImage { path, .. } => Some(path.clone()),
+                        _ => None,
+                    };
+                    let resp = self.resp.take();
+                    Some(WindowMsg::ShowImage {
+                        image,
+                        path,
+                        id,
+                        resp,
+                    })
+                }
+                Err(e) => {
+                    log::warn!("{e}");
+                    if let Some(resp) = self.resp.take() {
+                        let _ = resp.fail(e);
+                    }
+                    None
+                }
+            };
+        }
+
+        match &mut self.msg {
+            Msg::OpenWindow { name, props, .. } => {
+                let name = name.take();
+                let props = mem::take(props);
+                let resp = self.resp.take();
+                Some(WindowMsg::OpenWindow { name, props, resp })
+            }
+            Msg::SetWindowProps { id, props } => {
+                let id = id.clone();
+                let props = mem::take(props);
+                let resp = self.resp.take();
+                Some(WindowMsg::SetWindowProps { id, props, resp })
+            }
+            Msg::CloseWindow { id } => {
+                let id = id.clone();
+                let resp = self.resp.take();
+                Some(WindowMsg::CloseWindow { id, resp })
             }
-            Msg::OpenWindow { resp } => {
-                let resp = resp.take()?;
-                Some(WindowMsg::OpenWindow { resp })
+            Msg::SetPlaylist { id, paths, index } => {
+                let id = id.clone();
+                let playlist = Playlist::new(mem::take(paths), *index);
+                let resp = self.resp.take();
+                Some(WindowMsg::SetPlaylist { id, playlist, resp })
             }
+            Msg::Navigate { id, to } => {
+                let id = id.clone();
+                let resp = self.resp.take();
+                Some(WindowMsg::Navigate { id, to: *to, resp })
+            }
+            Msg::Zoom {
+                id,
+                factor,
+                relative,
+            } => {
+                let id = id.clone();
+                let resp = self.resp.take();
+                Some(WindowMsg::Zoom {
+                    id,
+                    factor: *factor,
+                    relative: *relative,
+                    resp,
+                })
+            }
+            Msg::Rotate { id, degrees } => {
+                let id = id.clone();
+                let resp = self.resp.take();
+                Some(WindowMsg::Rotate {
+                    id,
+                    degrees: *degrees,
+                    resp,
+                })
+            }
+            Msg::ListWindows {} => {
+                let resp = self.resp.take();
+                Some(WindowMsg::ListWindows { resp })
+            }
+            Msg::GetWindow { id } => {
+                let id = id.clone();
+                let resp = self.resp.take();
+                Some(WindowMsg::GetWindow { id, resp })
+            }
+            Msg::Shutdown {} => {
+                let resp = self.resp.take();
+                Some(WindowMsg::Shutdown { resp })
+            }
+            Msg::Subscribe { .. }
+            | Msg::ShowImage { .. }
+            | Msg::ShowImageData { .. }
+            | Msg::ShowImageRaw { .. }
+            | Msg::ShowImageFd { .. } => None,
         }
     }
 }
 
-impl SomeFrom<winit::event::Event<WindowMsg>> for WindowMsg {
-    fn some_from(value: winit::event::Event<WindowMsg>) -> Option<Self> {
+impl Msg {
+    /// Reads, decodes or maps the image of an image request along with where
+    /// it is to be shown.
+    pub(super) fn load_image(
+        &mut self,
+    ) -> Option<(SurfaceId, std::result::Result<RawImage, String>)> {
+        let loaded = match self {
+            Msg::ShowImage { path, id, .. } => {
+                let image = image::open(&*path)
+                    .map_err(|e| format!("failed to open image {path:?}: {e}"))
+                    .and_then(RawImage::try_from);
+                (id.clone(), image)
+            }
+            Msg::ShowImageData { data, id, .. } => {
+                let image = image::load_from_memory(data)
+                    .map_err(|e| format!("failed to decode image: {e}"))
+                    .and_then(RawImage::try_from);
+                (id.clone(), image)
+            }
+            Msg::ShowImageRaw {
+                width,
+                height,
+                format,
+                stride,
+                data,
+                id,
+                ..
+            } => {
+                let data = mem::take(data).into_boxed_slice();
+                let image = layout(*format, *width, *height, *stride).and_then(|info| {
+                    RawImage::new(info, data).ok_or_else(|| {
+                        format!("not enough data for a {width}x{height} {format:?} image")
+                    })
+                });
+                (id.clone(), image)
+            }
+            Msg::ShowImageFd {
+                width,
+                height,
+                format,
+                stride,
+                offset,
+                id,
+                fd,
+                ..
+            } => {
+                let image = layout(*format, *width, *height, *stride)
+                    .and_then(|info| map_image(info, *offset, fd.take()));
+                (id.clone(), image)
+            }
+            _ => return None,
+        };
+        Some(loaded)
+    }
+}
+
+/// How the pixels of an image sent by a client are laid out, failing for
+/// empty images and ones whose rows do not fit in their stride.
+pub(super) fn layout(
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    stride: Option<u32>,
+) -> std::result::Result<ImageInfo, String> {
+    let mut info = ImageInfo::new(format, width, height)
+        .ok_or_else(|| format!("a {width}x{height} {format:?} image is too wide"))?;
+    if info.is_empty() {
+        return Err(format!("a {width}x{height} image is empty"));
+    }
+    if let Some(stride) = stride {
+        info.stride.y = stride;
+    }
+    check_stride(info)?;
+    Ok(info)
+}
+
+fn check_stride(info: ImageInfo) -> std::result::Result<(), String> {
+    let row = info.stride.x.checked_mul(info.size.x);
+    if row.map_or(true, |row| info.stride.y < row) {
+        return Err(format!(
+            "stride {} is too small for {info:?}",
+            info.stride.y
+        ));
+    }
+    Ok(())
+}
+
+/// Maps the pixels of an image shared by a client.
+fn map_image(
+    info: ImageInfo,
+    offset: u64,
+    fd: Option<OwnedFd>,
+) -> std::result::Result<RawImage, String> {
+    let fd = fd.ok_or("no file descriptor was sent with the request")?;
+    check_stride(info)?;
+    check_sealed(&fd)?;
+
+    let file = File::from(fd);
+    let len = info.byte_len();
+    let needed = offset
+        .checked_add(len as u64)
+        .ok_or_else(|| format!("shared image at offset {offset} runs past the end of memory"))?;
+    let size = file
+        .metadata()
+        .map_err(|e| format!("failed to stat shared image: {e}"))?
+        .len();
+    if size < needed {
+        return Err(format!(
+            "shared image is {size} bytes but {needed} are needed"
+        ));
+    }
+
+    // # Safety
+    // the client can still write to the memory while it is mapped, which at
+    // worst tears the frame. it can not shrink it out from under us as that
+    // is sealed against
+    let map = unsafe { MmapOptions::new().offset(offset).len(len).map(&file) }
+        .map_err(|e| format!("failed to map shared image: {e}"))?;
+
+    RawImage::new(info, map).ok_or_else(|| "shared image is too small".to_string())
+}
+
+/// Fails unless `fd` is sealed against shrinking, since reading mapped memory
+/// that was truncated away kills the whole server.
+fn check_sealed(fd: &OwnedFd) -> std::result::Result<(), String> {
+    let seals = unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) };
+    if seals == -1 {
+        let e = std::io::Error::last_os_error();
+        return Err(format!("shared image has to be a sealed memfd: {e}"));
+    }
+    if seals & libc::F_SEAL_SHRINK == 0 {
+        return Err("shared image has to be sealed with F_SEAL_SHRINK".to_string());
+    }
+    Ok(())
+}
+
+/// A winit event, along with the keyboard modifiers held when it came.
+impl SomeFrom<(winit::event::Event<WindowMsg>, ModifiersState)> for WindowMsg {
+    fn some_from(
+        (value, modifiers): (winit::event::Event<WindowMsg>, ModifiersState),
+    ) -> Option<Self> {
         use winit::event::Event as W;
         use winit::event::StartCause as SrtC;
         use winit::event::WindowEvent as We;
@@ -50,12 +294,12 @@ impl SomeFrom<winit::event::Event<WindowMsg>> for WindowMsg {
                     id: window_id,
                     size: size.move_it(|s| UVec2::new(s.width, s.height)),
                 }),
-                We::Moved(_) => todo!(),
-                We::CloseRequested => Some(WindowMsg::CloseWindow { id: window_id }),
-                We::Destroyed => Some(WindowMsg::CloseWindow { id: window_id }),
+                We::Moved(_) => None,
+                We::CloseRequested => Some(WindowMsg::CloseRequested { id: window_id }),
+                We::Destroyed => Some(WindowMsg::CloseRequested { id: window_id }),
                 We::Focused(_) => None,
                 We::KeyboardInput { event, .. } => {
-                    Key::some_from(event).and_then(SomeInto::some_into)
+                    Key::some_from((event, modifiers)).and_then(|key| key.action(window_id))
                 }
                 We::RedrawRequested => Some(WindowMsg::WindowRedraw { id: window_id }),
                 We::ScaleFactorChanged { .. } => None,
@@ -66,7 +310,7 @@ impl SomeFrom<winit::event::Event<WindowMsg>> for WindowMsg {
                 We::MouseWheel { .. } => None,
                 We::MouseInput { .. } => None,
                 We::Ime(_) => None,
-                e => unimplemented!("event not handled yet: {e:?}"),
+                _ => None,
             },
             // TODO: have some init code ran
             W::NewEvents(SrtC::Init) => None,
@@ -87,3 +331,68 @@ impl SomeFrom<winit::event::Event<WindowMsg>> for WindowMsg {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::gpu::image::Alpha;
+    use std::io::Write;
+    use std::os::fd::FromRawFd;
+
+    fn memfd(data: &[u8], seals: libc::c_int) -> OwnedFd {
+        let fd = unsafe { libc::memfd_create(c"image".as_ptr(), libc::MFD_ALLOW_SEALING) };
+        assert!(fd >= 0);
+        let mut file = unsafe { File::from_raw_fd(fd) };
+        file.write_all(data).unwrap();
+        assert_eq!(unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, seals) }, 0);
+        file.into()
+    }
+
+    #[test]
+    fn maps_sealed_memfds_only() {
+        let info = ImageInfo::new(PixelFormat::Mono8, 2, 2).unwrap();
+
+        let fd = memfd(&[1, 2, 3, 4], libc::F_SEAL_SHRINK);
+        let image = map_image(info, 0, Some(fd)).unwrap();
+        assert_eq!(&image.data[..], [1, 2, 3, 4]);
+
+        let fd = memfd(&[1, 2, 3, 4], libc::F_SEAL_GROW);
+        assert!(map_image(info, 0, Some(fd)).is_err());
+    }
+
+    #[test]
+    fn rejects_empty_images() {
+        assert!(layout(PixelFormat::Mono8, 0, 4, None).is_err());
+        assert!(layout(PixelFormat::Mono8, 4, 0, None).is_err());
+
+        let info = ImageInfo::new(PixelFormat::Mono8, 0, 0).unwrap();
+        assert!(RawImage::new(info, Box::<[u8]>::default()).is_none());
+
+        let mut msg = Msg::ShowImageRaw {
+            width: 0,
+            height: 0,
+            format: PixelFormat::Mono8,
+            stride: None,
+            data: Vec::new(),
+            id: SurfaceId::Window(1),
+            placement: None,
+        };
+        let (_, image) = msg.load_image().unwrap();
+        assert!(image.is_err());
+    }
+
+    #[test]
+    fn rejects_sizes_that_overflow() {
+        let mut info = ImageInfo::new(PixelFormat::Rgba8(Alpha::Unpremultiplied), 1, 1).unwrap();
+        info.size.x = u32::MAX;
+        assert!(check_stride(info).is_err());
+
+        let rgba = PixelFormat::Rgba8(Alpha::Unpremultiplied);
+        assert!(layout(rgba, u32::MAX, 1, None).is_err());
+        assert!(layout(rgba, u32::MAX, 1, Some(u32::MAX)).is_err());
+
+        let info = ImageInfo::new(PixelFormat::Mono8, 2, 2).unwrap();
+        let fd = memfd(&[1, 2, 3, 4], libc::F_SEAL_SHRINK);
+        assert!(map_image(info, u64::MAX, Some(fd)).is_err());
+    }
+}
@@ -1,7 +1,13 @@
+pub mod client;
+pub mod console;
+pub mod instance;
+pub mod layer;
 pub mod logic;
+pub mod playlist;
 pub mod prelude;
 pub mod render;
 pub mod task;
+pub mod terminal;
 pub mod util;
 pub mod window;
 // mod mouse;
@@ -10,12 +16,22 @@ pub type ImvrEventLoop = winit::event_loop::EventLoop<WindowMsg>;
 pub type ImvrEventLoopHandle = winit::event_loop::EventLoopWindowTarget<WindowMsg>;
 pub type ImvrEventLoopProxy = winit::event_loop::EventLoopProxy<WindowMsg>;
 
+use crate::client::Client;
+use crate::instance::{InstanceError, InstanceLock};
+use crate::logic::msg::{Args, Command, LaunchError};
 use crate::prelude::*;
+use crate::terminal::{Protocol, Terminal};
+use clap::Parser;
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
 
 #[derive(Debug)]
 enum ImvrError {
     Resource,
     Cleanup,
+    Client,
+    Launch,
+    Terminal,
 }
 impl fmt::Display for ImvrError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -23,6 +39,9 @@ impl fmt::Display for ImvrError {
         match self {
             ImvrError::Resource => f.write_str("failed to init a nessisary resource"),
             ImvrError::Cleanup => f.write_str("failed to cleanup a resource"),
+            ImvrError::Client => f.write_str("failed to talk to the running server"),
+            ImvrError::Launch => f.write_str("failed to find what to open"),
+            ImvrError::Terminal => f.write_str("failed to show images in the terminal"),
         }
     }
 }
@@ -31,6 +50,79 @@ impl Context for ImvrError {}
 fn main() -> Result<(), ImvrError> {
     log::init();
 
+    let args = Args::parse();
+    let socket = crate::instance::socket_path(args.socket.as_deref(), &args.instance);
+
+    // printing to the terminal needs no server at all
+    if args.terminal {
+        let launch = args.launch().change_context(ImvrError::Launch)?;
+        let protocol = args.terminal_protocol.unwrap_or_else(Protocol::detect);
+        return crate::terminal::print(&launch, protocol, args.dither)
+            .change_context(ImvrError::Terminal);
+    }
+
+    // a stream is read by the process it is piped into
+    if args.server || args.stream.is_some() {
+        return server(args, socket);
+    }
+
+    if let Some(Command::Layer { parser, .. }) = args.command {
+        let client = connect(&socket)?;
+        return crate::layer::run(client, parser).change_context(ImvrError::Client);
+    }
+
+    // a server is only started for something to show or someone to talk to it
+    let launch = args.launch().change_context(ImvrError::Launch)?;
+    if launch.is_empty() && !args.attach {
+        return Err(Report::new(LaunchError::Empty)).change_context(ImvrError::Launch);
+    }
+    let mut client = connect(&socket)?;
+
+    // only an attached client is around to own its windows
+    let opened = client
+        .open(&launch, !args.attach)
+        .change_context(ImvrError::Client)?;
+    if args.attach {
+        // someone at a terminal gets a prompt, anything else gets the events
+        if std::io::stdin().is_terminal() {
+            crate::console::run(client, opened.last().copied())
+                .change_context(ImvrError::Client)?;
+        } else {
+            client.attach().change_context(ImvrError::Client)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Connects to the server listening on `socket`, starting one if there is
+/// none.
+fn connect(socket: &Path) -> Result<Client, ImvrError> {
+    match Client::connect(socket) {
+        Ok(client) => Ok(client),
+        Err(e) => {
+            log::info!("no server to connect to ({e}), starting one");
+            Client::spawn(socket).change_context(ImvrError::Client)
+        }
+    }
+}
+
+/// Runs the windows in this process and listens for clients.
+fn server(args: Args, socket: PathBuf) -> Result<(), ImvrError> {
+    let lock = match InstanceLock::acquire(&socket) {
+        Ok(lock) => Some(lock),
+        // a stream only needs its own window so it can do without the socket
+        Err(e)
+            if args.stream.is_some()
+                && matches!(e.current_context(), InstanceError::AlreadyRunning(_)) =>
+        {
+            log::info!("{e}, streaming without listening");
+            None
+        }
+        Err(e) => return Err(e).change_context(ImvrError::Resource),
+    };
+    let socket = lock.as_ref().map(|lock| lock.socket().to_path_buf());
+
     let event_loop = winit::event_loop::EventLoopBuilder::with_user_event()
         .build()
         .attach_printable("failed to create winit event loop")
@@ -43,15 +135,35 @@ fn main() -> Result<(), ImvrError> {
         .change_context(ImvrError::Resource)?;
 
     let (t, r) = tokio::sync::oneshot::channel();
+    let launch = args.launch().change_context(ImvrError::Launch)?;
+
+    // requests for the terminal are drawn on the one the server runs in, if
+    // it is not writing to a log
+    let protocol = args.terminal_protocol.unwrap_or_else(Protocol::detect);
+    let terminal = std::io::stdout()
+        .is_terminal()
+        .then(|| Terminal::new(std::io::stdout(), protocol, args.dither));
+
+    // window events flow from the render thread back to the socket clients
+    let (events, _) = tokio::sync::broadcast::channel(64);
+    let window_events = events.clone();
+
+    // requests come from the sources on the runtime and from the render
+    // thread itself when it needs an image loaded
+    let (requests, rx) = tokio::sync::mpsc::channel(4);
+    let window_requests = requests.clone();
+
     // run our tokio rt on a different base thread as the main thread is reserved
     // for ui on mac
     let tokio = std::thread::spawn(|| {
         let rt = rt;
 
-        rt.block_on(crate::task::logic(proxy, r))
+        rt.block_on(crate::task::logic(
+            proxy, r, requests, rx, launch, socket, events, terminal,
+        ))
     });
 
-    crate::task::window(event_loop)
+    crate::task::window(event_loop, window_events, args.persist, window_requests)
         .attach_printable("Window thread panicd. this is unrecoverable on MacOs so if you are reading this good job")
         .change_context(ImvrError::Cleanup)?;
 
@@ -65,5 +177,8 @@ fn main() -> Result<(), ImvrError> {
         .attach_printable("event handlrs encountered an error")
         .change_context(ImvrError::Cleanup)?;
 
+    // removes the socket now that nothing is listening on it
+    drop(lock);
+
     Ok(())
 }
@@ -0,0 +1,338 @@
+//! The list of images a window steps through and building one from the
+//! command line.
+
+use std::cmp::Ordering;
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+/// Where to move to in a [`Playlist`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Navigation {
+    /// The entry after the current one, wrapping around at the end.
+    Next,
+    /// The entry before the current one, wrapping around at the start.
+    Previous,
+    First,
+    Last,
+    /// The entry at this index, counting from 0.
+    Index(usize),
+}
+
+/// An ordered list of images and which one is being shown.
+#[derive(Debug, Clone, Default)]
+pub struct Playlist {
+    paths: Vec<PathBuf>,
+    index: usize,
+}
+
+impl Playlist {
+    /// Makes a playlist starting at `index`, clamped to the last entry.
+    pub fn new(paths: Vec<PathBuf>, index: usize) -> Self {
+        let index = index.min(paths.len().saturating_sub(1));
+        Self { paths, index }
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    #[inline]
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// The entry being shown, if there are any.
+    #[inline]
+    pub fn current(&self) -> Option<&Path> {
+        self.paths.get(self.index).map(PathBuf::as_path)
+    }
+
+    /// Moves to another entry and returns it. Returns `None` and stays put if
+    /// there is no such entry.
+    pub fn go(&mut self, to: Navigation) -> Option<&Path> {
+        let len = self.paths.len();
+        if len == 0 {
+            return None;
+        }
+
+        self.index = match to {
+            Navigation::Next => (self.index + 1) % len,
+            Navigation::Previous => (self.index + len - 1) % len,
+            Navigation::First => 0,
+            Navigation::Last => len - 1,
+            Navigation::Index(i) if i < len => i,
+            Navigation::Index(_) => return None,
+        };
+
+        self.current()
+    }
+}
+
+/// The order files from the command line are shown in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum Sort {
+    /// By path, with runs of digits compared as numbers so `2` comes before
+    /// `10`.
+    #[default]
+    Natural,
+    /// Oldest modification first.
+    Mtime,
+    /// Smallest first.
+    Size,
+    /// Shuffled.
+    Random,
+}
+
+impl Sort {
+    pub fn sort(self, paths: &mut [PathBuf]) {
+        match self {
+            Sort::Natural => {
+                paths.sort_by(|a, b| natural_cmp(&a.to_string_lossy(), &b.to_string_lossy()))
+            }
+            // files that can not be read sort first, they are skipped when
+            // shown anyway
+            Sort::Mtime => {
+                paths.sort_by_cached_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+            }
+            Sort::Size => paths.sort_by_cached_key(|p| fs::metadata(p).map(|m| m.len()).ok()),
+            Sort::Random => {
+                use rand::seq::SliceRandom;
+                paths.shuffle(&mut rand::thread_rng())
+            }
+        }
+    }
+}
+
+/// Replaces every directory in `files` with the images in it, descending into
+/// subdirectories when `recursive`, sorted by `sort`.
+///
+/// Files given directly keep the order they were given in, and are kept even
+/// if they do not look like images so loading them can say what is wrong.
+pub fn expand(files: &[PathBuf], recursive: bool, sort: Sort) -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+    for file in files {
+        if file.is_dir() {
+            let mut found = Vec::new();
+            read_images(file, recursive, &mut found);
+            sort.sort(&mut found);
+            paths.extend(found);
+        } else {
+            paths.push(file.clone());
+        }
+    }
+    paths
+}
+
+fn read_images(dir: &Path, recursive: bool, paths: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::warn!("skipping {dir:?}: {e}");
+            return;
+        }
+    };
+
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if entry.file_name().to_string_lossy().starts_with('.') {
+            continue;
+        }
+        // symlinks are not followed into so a loop can not trap us
+        match entry.file_type() {
+            Ok(t) if t.is_dir() => {
+                if recursive {
+                    read_images(&path, recursive, paths);
+                }
+            }
+            Ok(_) if is_image(&path) => paths.push(path),
+            Ok(_) => log::debug!("skipping {path:?}: not an image"),
+            Err(e) => log::warn!("skipping {path:?}: {e}"),
+        }
+    }
+}
+
+/// Whether `path` is something `image` can decode, going by its extension or
+/// failing that the first few bytes.
+fn is_image(path: &Path) -> bool {
+    if image::ImageFormat::from_path(path).is_ok() {
+        return true;
+    }
+
+    let mut magic = Vec::with_capacity(16);
+    let read = fs::File::open(path).and_then(|f| f.take(16).read_to_end(&mut magic));
+    read.is_ok() && image::guess_format(&magic).is_ok()
+}
+
+/// Compares strings treating runs of ascii digits as numbers.
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let (mut a, mut b) = (a, b);
+    loop {
+        let (Some(x), Some(y)) = (a.chars().next(), b.chars().next()) else {
+            return a.len().cmp(&b.len());
+        };
+
+        if x.is_ascii_digit() && y.is_ascii_digit() {
+            let (an, arest) = split_digits(a);
+            let (bn, brest) = split_digits(b);
+            // leading zeros do not change the value but do break ties
+            let (at, bt) = (an.trim_start_matches('0'), bn.trim_start_matches('0'));
+            let ord = at
+                .len()
+                .cmp(&bt.len())
+                .then_with(|| at.cmp(bt))
+                .then_with(|| an.len().cmp(&bn.len()));
+            if ord != Ordering::Equal {
+                return ord;
+            }
+            (a, b) = (arest, brest);
+        } else {
+            if x != y {
+                return x.cmp(&y);
+            }
+            (a, b) = (&a[x.len_utf8()..], &b[y.len_utf8()..]);
+        }
+    }
+}
+
+fn split_digits(s: &str) -> (&str, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    s.split_at(end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn numbers_sort_by_value() {
+        let mut paths = [
+            "img10.png",
+            "img2.png",
+            "img1.png",
+            "a/img02.png",
+            "img2b.png",
+        ]
+        .map(PathBuf::from);
+        Sort::Natural.sort(&mut paths);
+        assert_eq!(
+            paths,
+            [
+                "a/img02.png",
+                "img1.png",
+                "img2.png",
+                "img2b.png",
+                "img10.png"
+            ]
+            .map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("x007", "x7"), Ordering::Greater);
+        assert_eq!(natural_cmp("x007", "x8"), Ordering::Less);
+        assert_eq!(natural_cmp("x7", "x7"), Ordering::Equal);
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+    /// A directory of its own for a test, removed again when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("imvr-{}-{name}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn file(&self, name: &str, data: &[u8]) -> PathBuf {
+            let path = self.0.join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, data).unwrap();
+            path
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn finds_images_by_extension_or_contents() {
+        let dir = TempDir::new("filter");
+        let named = dir.file("a.png", b"");
+        let sniffed = dir.file("b", PNG);
+        dir.file("notes.txt", b"not an image");
+        dir.file(".hidden.png", PNG);
+        dir.file(".cache/c.png", PNG);
+
+        let paths = expand(&[dir.0.clone()], true, Sort::Natural);
+        assert_eq!(paths, [named, sniffed]);
+    }
+
+    #[test]
+    fn recurses_only_when_asked() {
+        let dir = TempDir::new("recurse");
+        let top = dir.file("a.png", PNG);
+        let nested = dir.file("sub/deeper/b.png", PNG);
+
+        assert_eq!(
+            expand(&[dir.0.clone()], false, Sort::Natural),
+            [top.clone()]
+        );
+        assert_eq!(expand(&[dir.0.clone()], true, Sort::Natural), [top, nested]);
+    }
+
+    #[test]
+    fn sorts_by_mtime_and_size() {
+        use std::time::{Duration, SystemTime};
+
+        let dir = TempDir::new("sort");
+        let files =
+            [("a.png", 3, 20), ("b.png", 1, 30), ("c.png", 2, 10)].map(|(name, size, secs)| {
+                let path = dir.file(name, &vec![0; size]);
+                let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+                let file = fs::File::options().write(true).open(&path).unwrap();
+                file.set_modified(mtime).unwrap();
+                path
+            });
+        let [a, b, c] = files;
+
+        let by_mtime = expand(&[dir.0.clone()], false, Sort::Mtime);
+        assert_eq!(by_mtime, [c.clone(), a.clone(), b.clone()]);
+        let by_size = expand(&[dir.0.clone()], false, Sort::Size);
+        assert_eq!(by_size, [b, c, a]);
+    }
+
+    #[test]
+    fn files_given_directly_keep_their_order() {
+        let dir = TempDir::new("order");
+        let first = dir.file("z.png", PNG);
+        let last = dir.file("a.png", PNG);
+        let found = [
+            dir.file("images/2.png", PNG),
+            dir.file("images/10.png", PNG),
+        ];
+
+        let paths = expand(
+            &[first.clone(), dir.0.join("images"), last.clone()],
+            false,
+            Sort::Natural,
+        );
+        assert_eq!(paths, [first, found[0].clone(), found[1].clone(), last]);
+    }
+}
@@ -1,7 +1,13 @@
 use crate::prelude::*;
 
-use crate::window::Window;
+use crate::logic::msg::{Event, EventKind, Msg, Request, ReturnAddress, Size, WindowProps};
+use crate::logic::SurfaceId;
+use crate::playlist::Navigation;
+use crate::window::{Window, WindowError, WindowInfo};
 use crate::ImvrEventLoopHandle;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use tokio::sync::{broadcast, mpsc};
 use winit::window::WindowId;
 
 #[derive(Debug)]
@@ -9,6 +15,18 @@ pub enum GlobalContextError {
     Fatal,
     SendError,
     NoMatchingWindow(WindowId),
+    NoSuchWindow(u64),
+    NoSuchName(String),
+    NameTaken(String),
+    NotAWindow,
+    NotInPlaylist(Navigation),
+    BadZoom(f32),
+    BadAngle(f32),
+    EmptySize,
+    SizeTooLarge(u32),
+    SizeOutOfBounds,
+    CanNotOpen,
+    CanNotResize(WindowId),
 }
 
 impl GlobalContextError {
@@ -27,26 +45,135 @@ impl fmt::Display for GlobalContextError {
             GlobalContextError::NoMatchingWindow(id) => {
                 write!(f, "no matching window for id {id:?}")
             }
+            GlobalContextError::NoSuchWindow(id) => write!(f, "no window has id {id}"),
+            GlobalContextError::NoSuchName(name) => write!(f, "no window is named {name:?}"),
+            GlobalContextError::NameTaken(name) => {
+                write!(f, "a window is already named {name:?}")
+            }
+            GlobalContextError::NotAWindow => f.write_str("surface is not a window"),
+            GlobalContextError::NotInPlaylist(to) => {
+                write!(f, "playlist has no entry for {to:?}")
+            }
+            GlobalContextError::BadZoom(factor) => {
+                write!(f, "zoom has to be more than 0, got {factor}")
+            }
+            GlobalContextError::BadAngle(degrees) => write!(f, "can not rotate by {degrees}"),
+            GlobalContextError::EmptySize => f.write_str("window sizes can not be 0"),
+            GlobalContextError::SizeTooLarge(max) => {
+                write!(f, "window sizes can not be more than {max}")
+            }
+            GlobalContextError::SizeOutOfBounds => {
+                f.write_str("window size has to be between its min and max size")
+            }
+            GlobalContextError::CanNotOpen => f.write_str("unable to open a new window"),
+            GlobalContextError::CanNotResize(id) => write!(f, "unable to resize window {id:?}"),
         }
     }
 }
 impl Context for GlobalContextError {}
 
+/// Fails if `props` would size a window to nothing or to more than `max`
+/// pixels across, neither of which it can draw to, or gives it a size outside
+/// its own min and max size.
+fn check_sizes(props: &WindowProps, max: u32) -> Result<(), GlobalContextError> {
+    let sizes = [props.size, props.min_size, props.max_size];
+    for size in sizes.iter().flatten() {
+        if size.width == 0 || size.height == 0 {
+            return Err(Report::new(GlobalContextError::EmptySize));
+        }
+        if size.width > max || size.height > max {
+            return Err(Report::new(GlobalContextError::SizeTooLarge(max)));
+        }
+    }
+
+    let fits = |small: Option<Size>, large: Option<Size>| match (small, large) {
+        (Some(small), Some(large)) => small.width <= large.width && small.height <= large.height,
+        _ => true,
+    };
+    let bounded = fits(props.min_size, props.size)
+        && fits(props.size, props.max_size)
+        && fits(props.min_size, props.max_size);
+    match bounded {
+        true => Ok(()),
+        false => Err(Report::new(GlobalContextError::SizeOutOfBounds)),
+    }
+}
+
 /// The Global Context managing the windows and msgs to them
-#[derive(Debug, Default)]
+#[derive(Debug)]
 pub struct GlobalContext {
     /// The wgpu instance to create surfaces with.
     pub instance: wgpu::Instance,
 
     /// The windows.
     pub windows: Vec<Window>,
+
+    /// The id given to the next window opened.
+    ///
+    /// These belong to imvr rather than winit so they count up from 1 in
+    /// every session and are never handed out twice.
+    next_id: u64,
+
+    /// The winit window behind each id clients know about.
+    ids: HashMap<u64, WindowId>,
+
+    /// The windows that were given a name when they were opened.
+    names: HashMap<String, u64>,
+
+    /// Where to tell subscribed clients about what happens to the windows.
+    events: broadcast::Sender<Event>,
+
+    /// Keep running once the last window closes rather than exiting.
+    persist: bool,
+
+    /// The widest or tallest a window can be. Every device is asked for the
+    /// default limits, so none can draw to a larger surface than those allow.
+    max_size: u32,
+
+    /// Where images to show are sent to be loaded, so stepping through a
+    /// playlist does not decode on this thread.
+    requests: mpsc::Sender<Request>,
 }
 
 impl GlobalContext {
     /// Creates a new global context
     #[inline]
-    pub fn new() -> Self {
-        Self::default()
+    pub fn new(
+        events: broadcast::Sender<Event>,
+        persist: bool,
+        requests: mpsc::Sender<Request>,
+    ) -> Self {
+        Self {
+            instance: wgpu::Instance::default(),
+            windows: Vec::new(),
+            next_id: 1,
+            ids: HashMap::new(),
+            names: HashMap::new(),
+            events,
+            persist,
+            max_size: wgpu::Limits::default().max_texture_dimension_2d,
+            requests,
+        }
+    }
+
+    /// Tells subscribed clients about a window event, if it is one they can
+    /// subscribe to.
+    pub fn emit(&self, event: &winit::event::Event<WindowMsg>) {
+        let winit::event::Event::WindowEvent { window_id, event } = event else {
+            return;
+        };
+        let Some(window) = self.surface_id(*window_id) else {
+            return;
+        };
+        if let Some(kind) = EventKind::some_from(event) {
+            self.send_event(Event { window, kind });
+        }
+    }
+
+    #[inline]
+    fn send_event(&self, event: Event) {
+        // this only fails when nobody is subscribed
+        let _ = self.events.send(event);
     }
 
     pub fn handle(
@@ -61,55 +188,345 @@ impl GlobalContext {
                     self.handle(req, evwt)?;
                 }
             }
-            W::ShowImage { image, id } => self.get_window_mut(id)?.set_image(image),
+            W::ShowImage {
+                image,
+                path,
+                id,
+                resp,
+            } => {
+                let res = self
+                    .resolve(&id)
+                    .and_then(|id| self.get_window_mut(id))
+                    .map(|w| w.set_image(image, path));
+                if let Some(resp) = resp {
+                    let _ = resp.finish(&res);
+                }
+                res?
+            }
+            W::Shutdown { resp } => {
+                log::info!("shutting down on request");
+                if let Some(resp) = resp {
+                    let _ = resp.finish::<GlobalContextError>(&Ok(()));
+                }
+                evwt.exit();
+            }
             W::Exit => {
                 // TODO: join all the processing threads
                 evwt.exit();
             }
-            W::Resize { size, id } => self.get_window_mut(id)?.resize(size),
+            // minimizing resizes to nothing, there is no surface to fit then
+            W::Resize { size, .. } if size.x == 0 || size.y == 0 => {}
+            W::Resize { size, id } => self
+                .get_window_mut(id)?
+                .resize(size)
+                .change_context(GlobalContextError::CanNotResize(id))?,
             W::WindowRedraw { id } => self
                 .get_window_mut(id)?
                 .render()
                 .change_context(GlobalContextError::Fatal)?,
-            W::OpenWindow { resp } => {
-                log::debug!("imvr: creating window");
-
-                let window = Window::new("image", evwt, &self.instance)
-                    .attach_printable("unable to make a new window")
-                    .change_context(GlobalContextError::Fatal)?;
-
-                let id = window.id().into();
-                self.windows.push(window);
-
-                log::info!("imvr: created window {}", id);
-
-                resp.send(id)
-                    .attach_printable_lazy(|| format!("Could not send id ({id}) on channel."))
-                    .change_context(GlobalContextError::SendError)?;
-            }
-            W::CloseWindow { id } => {
-                let index = self
-                    .windows
-                    .iter()
-                    .enumerate()
-                    .find(|(_, w)| w.id() == id)
-                    .ok_or(Report::new(GlobalContextError::NoMatchingWindow(id)))
-                    .attach_printable("cant remove window")?
-                    .0;
-                log::debug!("closing window {:?}", id);
-
-                let window = self.windows.swap_remove(index);
-                // TODO: do clean up the window
-                drop(window);
-
-                if self.windows.is_empty() {
-                    evwt.exit()
+            W::OpenWindow { name, props, resp } => {
+                self.open_window(name, props, resp, |title, props, instance| {
+                    Window::new(title, props, evwt, instance)
+                })?
+            }
+            W::CloseWindow { id, resp } => {
+                let res = self.resolve(&id).and_then(|id| self.close_window(id, evwt));
+                if let Some(resp) = resp {
+                    let _ = resp.finish(&res);
+                }
+                res?
+            }
+            W::SetWindowProps { id, props, resp } => {
+                let res = check_sizes(&props, self.max_size)
+                    .and_then(|()| self.resolve(&id))
+                    .and_then(|id| self.get_window_mut(id))
+                    .map(|w| w.set_props(props));
+                if let Some(resp) = resp {
+                    let _ = resp.finish(&res);
+                }
+                res?
+            }
+            W::CloseRequested { id } => self.close_window(id, evwt)?,
+            W::Zoom {
+                id,
+                factor,
+                relative,
+                resp,
+            } => {
+                let res = match factor.is_finite() && factor > 0.0 {
+                    true => self
+                        .resolve(&id)
+                        .and_then(|id| self.get_window_mut(id))
+                        .map(|w| w.zoom(factor, relative)),
+                    false => Err(Report::new(GlobalContextError::BadZoom(factor))),
+                };
+                if let Some(resp) = resp {
+                    let _ = resp.finish(&res);
+                }
+                res?
+            }
+            W::Rotate { id, degrees, resp } => {
+                let res = match degrees.is_finite() {
+                    true => self
+                        .resolve(&id)
+                        .and_then(|id| self.get_window_mut(id))
+                        .map(|w| w.rotate(degrees)),
+                    false => Err(Report::new(GlobalContextError::BadAngle(degrees))),
+                };
+                if let Some(resp) = resp {
+                    let _ = resp.finish(&res);
+                }
+                res?
+            }
+            W::SetPlaylist { id, playlist, resp } => {
+                let entry = self.surface(&id).and_then(|surface| {
+                    let window = self.get_window_mut(self.ids[&surface])?;
+                    window.playlist = playlist;
+                    Ok((surface, window.playlist.current().map(PathBuf::from)))
+                });
+                self.show_entry(entry, resp)?
+            }
+            W::Navigate { id, to, resp } => {
+                let entry = self
+                    .surface(&id)
+                    .and_then(|surface| self.navigate(surface, to));
+                self.show_entry(entry, resp)?
+            }
+            W::NavigateWindow { id, to } => {
+                let surface = self
+                    .surface_id(id)
+                    .ok_or_else(|| Report::new(GlobalContextError::NoMatchingWindow(id)))?;
+                let entry = self.navigate(surface, to);
+                self.show_entry(entry, None)?
+            }
+            W::ListWindows { resp } => {
+                let mut ids: Vec<u64> = self.ids.keys().copied().collect();
+                ids.sort_unstable();
+                let windows = ids
+                    .into_iter()
+                    .map(|id| self.describe(id))
+                    .collect::<Result<Vec<_>, _>>();
+                if let Some(resp) = resp {
+                    let _ = match &windows {
+                        Ok(windows) => resp.value(windows),
+                        Err(e) => resp.fail(e),
+                    };
+                }
+                windows?;
+            }
+            W::GetWindow { id, resp } => {
+                let info = self.surface(&id).and_then(|id| self.describe(id));
+                if let Some(resp) = resp {
+                    let _ = match &info {
+                        Ok(info) => resp.value(info),
+                        Err(e) => resp.fail(e),
+                    };
                 }
+                info?;
             }
         }
         Ok(())
     }
 
+    /// Opens a window made by `create` and answers `resp` with its id, or
+    /// rejects the request if `name` is taken or `props` can not be shown.
+    fn open_window(
+        &mut self,
+        name: Option<String>,
+        props: WindowProps,
+        resp: Option<ReturnAddress>,
+        create: impl FnOnce(&str, WindowProps, &wgpu::Instance) -> Result<Window, WindowError>,
+    ) -> Result<(), GlobalContextError> {
+        log::debug!("imvr: creating window");
+
+        let checked = match name.as_ref().filter(|n| self.names.contains_key(*n)) {
+            Some(name) => Err(Report::new(GlobalContextError::NameTaken(name.clone()))),
+            None => check_sizes(&props, self.max_size),
+        };
+        let title = name.as_deref().unwrap_or("image");
+        let window = checked.and_then(|()| {
+            create(title, props, &self.instance)
+                .attach_printable("unable to make a new window")
+                .change_context(GlobalContextError::CanNotOpen)
+        });
+        let window = match window {
+            Ok(window) => window,
+            Err(e) => {
+                if let Some(resp) = resp {
+                    let _ = resp.fail(&e);
+                }
+                return Err(e);
+            }
+        };
+
+        let id = self.add_surface(window.id(), name);
+        self.windows.push(window);
+
+        log::info!("imvr: created window {}", id);
+        self.send_event(Event {
+            window: id,
+            kind: EventKind::Opened,
+        });
+
+        if let Some(resp) = resp {
+            resp.send(id)
+                .attach_printable_lazy(|| format!("Could not send id ({id}) on channel."))
+                .change_context(GlobalContextError::SendError)?;
+        }
+        Ok(())
+    }
+
+    fn close_window(
+        &mut self,
+        id: WindowId,
+        evwt: &ImvrEventLoopHandle,
+    ) -> Result<(), GlobalContextError> {
+        let index = self
+            .windows
+            .iter()
+            .enumerate()
+            .find(|(_, w)| w.id() == id)
+            .ok_or(Report::new(GlobalContextError::NoMatchingWindow(id)))
+            .attach_printable("cant remove window")?
+            .0;
+        log::debug!("closing window {:?}", id);
+
+        let window = self.windows.swap_remove(index);
+        // TODO: do clean up the window
+        drop(window);
+
+        if let Some(surface) = self.remove_surface(id) {
+            self.send_event(Event {
+                window: surface,
+                kind: EventKind::Closed,
+            });
+        }
+
+        if self.windows.is_empty() && !self.persist {
+            evwt.exit()
+        }
+
+        Ok(())
+    }
+
+    /// Finds the window a client is refering to.
+    pub fn resolve(&self, id: &SurfaceId) -> Result<WindowId, GlobalContextError> {
+        let id = self.surface(id)?;
+        Ok(self.ids[&id])
+    }
+
+    /// The id of the open window a client is refering to.
+    fn surface(&self, id: &SurfaceId) -> Result<u64, GlobalContextError> {
+        match id {
+            SurfaceId::Terminal => Err(Report::new(GlobalContextError::NotAWindow)),
+            SurfaceId::Window(id) if self.ids.contains_key(id) => Ok(*id),
+            SurfaceId::Window(id) => Err(Report::new(GlobalContextError::NoSuchWindow(*id))),
+            SurfaceId::Name(name) => self
+                .names
+                .get(name)
+                .copied()
+                .ok_or_else(|| Report::new(GlobalContextError::NoSuchName(name.clone()))),
+        }
+    }
+
+    /// Describes an open window.
+    fn describe(&self, id: u64) -> Result<WindowInfo, GlobalContextError> {
+        let window = self
+            .ids
+            .get(&id)
+            .ok_or_else(|| Report::new(GlobalContextError::NoSuchWindow(id)))
+            .and_then(|w| self.get_window(*w))?;
+        let name = self
+            .names
+            .iter()
+            .find(|(_, s)| **s == id)
+            .map(|(name, _)| name.clone());
+        Ok(window.info(id, name))
+    }
+
+    /// Moves a window through its playlist, returning the entry to show.
+    fn navigate(
+        &mut self,
+        surface: u64,
+        to: Navigation,
+    ) -> Result<(u64, Option<PathBuf>), GlobalContextError> {
+        let window = self.get_window_mut(self.ids[&surface])?;
+        let path = window
+            .playlist
+            .go(to)
+            .ok_or_else(|| Report::new(GlobalContextError::NotInPlaylist(to)))?;
+        Ok((surface, Some(path.to_path_buf())))
+    }
+
+    /// Has a playlist entry loaded and shown, answering `resp` once it is.
+    fn show_entry(
+        &self,
+        entry: Result<(u64, Option<PathBuf>), GlobalContextError>,
+        resp: Option<ReturnAddress>,
+    ) -> Result<(), GlobalContextError> {
+        let (surface, path) = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                if let Some(resp) = resp {
+                    let _ = resp.fail(&e);
+                }
+                return Err(e);
+            }
+        };
+
+        let Some(path) = path else {
+            // an empty playlist leaves the window as it is
+            if let Some(resp) = resp {
+                let _ = resp.finish::<GlobalContextError>(&Ok(()));
+            }
+            return Ok(());
+        };
+
+        let req = Request {
+            msg: Msg::ShowImage {
+                path,
+                id: SurfaceId::Window(surface),
+                placement: None,
+            },
+            resp,
+        };
+        // the logic thread never waits on this one so blocking can not deadlock
+        self.requests
+            .blocking_send(req)
+            .map_err(|_| Report::new(GlobalContextError::SendError))
+            .attach_printable("logic thread is no longer taking requests")
+    }
+
+    /// Gives a newly opened winit window the next id, and `name` if it was
+    /// given one.
+    fn add_surface(&mut self, window: WindowId, name: Option<String>) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        self.ids.insert(id, window);
+        if let Some(name) = name {
+            self.names.insert(name, id);
+        }
+        id
+    }
+
+    /// Forgets the id and name of a winit window that closed, returning the
+    /// id it had.
+    fn remove_surface(&mut self, window: WindowId) -> Option<u64> {
+        let surface = self.surface_id(window)?;
+        self.ids.remove(&surface);
+        self.names.retain(|_, s| *s != surface);
+        Some(surface)
+    }
+
+    /// The id clients know a winit window by.
+    fn surface_id(&self, id: WindowId) -> Option<u64> {
+        self.ids
+            .iter()
+            .find(|(_, w)| **w == id)
+            .map(|(surface, _)| *surface)
+    }
+
     #[inline]
     pub fn get_window_mut(&mut self, id: WindowId) -> Result<&mut Window, GlobalContextError> {
         self.windows
@@ -126,3 +543,68 @@ impl GlobalContext {
             .ok_or(Report::new(GlobalContextError::NoMatchingWindow(id)))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn context() -> GlobalContext {
+        let (events, _) = broadcast::channel(4);
+        let (requests, _) = mpsc::channel(4);
+        GlobalContext::new(events, false, requests)
+    }
+
+    #[test]
+    fn ids_count_up_and_are_never_reused() {
+        let mut ctx = context();
+        let (a, b) = (WindowId::from(10), WindowId::from(20));
+        let name = || SurfaceId::Name("preview".into());
+
+        assert_eq!(ctx.add_surface(a, Some("preview".into())), 1);
+        assert_eq!(ctx.add_surface(b, None), 2);
+
+        assert_eq!(ctx.remove_surface(a), Some(1));
+        assert!(ctx.surface(&SurfaceId::Window(1)).is_err());
+        assert!(ctx.surface(&name()).is_err());
+
+        // even a window winit gives the same id again is a new one to clients
+        assert_eq!(ctx.add_surface(a, Some("preview".into())), 3);
+        assert_eq!(ctx.surface(&name()).unwrap(), 3);
+        assert!(ctx.surface(&SurfaceId::Window(1)).is_err());
+        assert_eq!(ctx.resolve(&SurfaceId::Window(2)).unwrap(), b);
+    }
+    #[test]
+    fn windows_too_large_to_draw_are_rejected() {
+        let mut ctx = context();
+        let too_large = Size {
+            width: ctx.max_size + 1,
+            height: 100,
+        };
+        let props = WindowProps {
+            size: Some(too_large),
+            ..Default::default()
+        };
+        let (tx, mut rx) = tokio::sync::oneshot::channel();
+        let res = ctx.open_window(None, props, Some(ReturnAddress::Memory(tx)), |_, _, _| {
+            unreachable!("a window too large to draw was made")
+        });
+
+        let e = res.unwrap_err();
+        assert!(!e.current_context().is_fatal());
+        assert!(rx.try_recv().unwrap().is_err());
+        assert!(ctx.windows.is_empty());
+
+        let props = WindowProps {
+            size: Some(Size {
+                width: 200,
+                height: 200,
+            }),
+            max_size: Some(Size {
+                width: 100,
+                height: 100,
+            }),
+            ..Default::default()
+        };
+        assert!(check_sizes(&props, ctx.max_size).is_err());
+    }
+}
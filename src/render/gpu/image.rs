@@ -15,7 +15,7 @@ pub struct ImageInfo {
 }
 
 /// Supported pixel formats.
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 #[allow(unused)]
 pub enum PixelFormat {
     /// 8-bit monochrome data.
@@ -58,7 +58,7 @@ impl From<ColorType> for PixelFormat {
 /// Possible alpha representations.
 ///
 /// See also: <https://en.wikipedia.org/wiki/Alpha_compositing#Straight_versus_premultiplied>
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, serde::Serialize, serde::Deserialize)]
 #[allow(unused)]
 pub enum Alpha {
     /// The alpha channel is encoded only in the alpha component of the pixel.
@@ -71,16 +71,27 @@ pub enum Alpha {
 impl ImageInfo {
     /// Create a new info struct with the given format, width and height.
     ///
-    /// The row stride is automatically calculated based on the image width and pixel format.
+    /// The row stride is automatically calculated based on the image width and pixel format,
+    /// giving `None` if a row would take more than `u32::MAX` bytes.
     /// If you wish to use a different row stride, construct the struct directly.
-    pub fn new(pixel_format: PixelFormat, width: u32, height: u32) -> Self {
+    pub fn new(pixel_format: PixelFormat, width: u32, height: u32) -> Option<Self> {
         let stride_x = u32::from(pixel_format.bytes_per_pixel());
-        let stride_y = stride_x * width;
-        Self {
+        let stride_y = stride_x.checked_mul(width)?;
+        Some(Self {
             pixel_format,
             size: ext::glam::UVec2::new(width, height),
             stride: ext::glam::UVec2::new(stride_x, stride_y),
-        }
+        })
+    }
+
+    /// Whether the image has no pixels at all.
+    pub fn is_empty(&self) -> bool {
+        self.size.x == 0 || self.size.y == 0
+    }
+
+    /// The number of bytes needed to hold an image with this layout.
+    pub fn byte_len(&self) -> usize {
+        self.stride.y as usize * self.size.y as usize
     }
 }
 
@@ -213,7 +224,7 @@ impl GpuImage {
         let data = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some(&format!("{}_image_buffer", name)),
             contents: image.data(),
-            usage: wgpu::BufferUsages::STORAGE,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
@@ -248,6 +259,39 @@ impl GpuImage {
         }
     }
 
+    /// Overwrites the pixels with those of an image with the same layout so
+    /// a new frame does not need new buffers.
+    ///
+    /// Returns `false` without writing anything if the layouts differ.
+    pub fn write(&self, queue: &wgpu::Queue, image: &ImageView) -> bool {
+        if image.info() != self.info {
+            return false;
+        }
+
+        let len = self.info.byte_len();
+        let Some(data) = image.data().get(..len) else {
+            return false;
+        };
+        // writes have to be whole words, the buffer was padded out to one
+        // when it was made
+        let padded = len.next_multiple_of(wgpu::COPY_BUFFER_ALIGNMENT as usize) as u64;
+        let Some(size) = wgpu::BufferSize::new(padded) else {
+            // nothing to write for an empty image
+            return true;
+        };
+        if size.get() > self.data.size() {
+            return false;
+        }
+
+        match queue.write_buffer_with(&self.data, 0, size) {
+            Some(mut view) => {
+                view[..len].copy_from_slice(data);
+                true
+            }
+            None => false,
+        }
+    }
+
     /// Get the image info.
     pub fn info(&self) -> &ImageInfo {
         &self.info
@@ -42,24 +42,40 @@ impl WindowUniforms {
         unsafe { mem::transmute(self.size) }
     }
 
-    pub fn fit(window_size: Vec2, image_size: Vec2) -> Self {
-        let ratios = image_size / window_size;
-
-        let w;
-        let h;
-        if ratios.x >= ratios.y {
-            w = 1.0;
-            h = ratios.y / ratios.x;
+    /// Centres the image in the window, rotated clockwise by `rotation`
+    /// radians and then either fitted inside the window or stretched to fill
+    /// it, and finally scaled by `zoom`.
+    pub fn place(
+        window_size: Vec2,
+        image_size: Vec2,
+        preserve_aspect_ratio: bool,
+        zoom: f32,
+        rotation: f32,
+    ) -> Self {
+        let (sin, cos) = rotation.sin_cos();
+
+        // the size of the unit square before it is rotated, in pixels
+        let scale = if preserve_aspect_ratio {
+            // the box the rotated image takes up has to fit the window
+            let bounds = Vec2::new(
+                cos.abs() * image_size.x + sin.abs() * image_size.y,
+                sin.abs() * image_size.x + cos.abs() * image_size.y,
+            );
+            let fit = (window_size / bounds).min_element();
+            image_size * fit
+        } else if sin.abs() > cos.abs() {
+            // on its side the image fills the window the other way round
+            Vec2::new(window_size.y, window_size.x)
         } else {
-            w = ratios.x / ratios.y;
-            h = 1.0;
-        }
+            window_size
+        };
 
-        let transform = Affine2::from_scale_angle_translation(
-            Vec2::new(w, h),
-            0.0,
-            0.5 * Vec2::new(1.0 - w, 1.0 - h),
-        );
+        // y points down so a positive angle turns clockwise on screen
+        let pixels = Affine2::from_translation(0.5 * window_size)
+            * Affine2::from_angle(rotation)
+            * Affine2::from_scale(scale * zoom)
+            * Affine2::from_translation(Vec2::splat(-0.5));
+        let transform = Affine2::from_scale(window_size.recip()) * pixels;
 
         Self {
             transform: transform.into(),
@@ -170,3 +186,62 @@ impl From<Affine2> for Mat3x3 {
         ])
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::f32::consts::FRAC_PI_2;
+
+    const WINDOW: Vec2 = Vec2::new(200.0, 100.0);
+
+    /// Where the top left and bottom right corners of the image end up, in
+    /// pixels of the window.
+    fn corners(uniforms: WindowUniforms) -> [Vec2; 2] {
+        let [x, y, t] = uniforms.transform.cols;
+        let apply = |p: Vec2| {
+            let normalized = Vec2::new(x.x, x.y) * p.x + Vec2::new(y.x, y.y) * p.y;
+            (normalized + Vec2::new(t.x, t.y)) * WINDOW
+        };
+        [apply(Vec2::ZERO), apply(Vec2::ONE)]
+    }
+
+    fn assert_corners(uniforms: WindowUniforms, expected: [[f32; 2]; 2]) {
+        let got = corners(uniforms);
+        for (got, expected) in got.iter().zip(expected) {
+            let expected = Vec2::from(expected);
+            assert!(got.distance(expected) < 1e-3, "{got:?} != {expected:?}");
+        }
+    }
+
+    #[test]
+    fn fits_inside_the_window() {
+        let square = Vec2::new(100.0, 100.0);
+        let uniforms = WindowUniforms::place(WINDOW, square, true, 1.0, 0.0);
+        assert_corners(uniforms, [[50.0, 0.0], [150.0, 100.0]]);
+    }
+
+    #[test]
+    fn fits_on_its_side_when_rotated() {
+        // the top left corner goes to the top right, and the image stands up
+        let wide = Vec2::new(100.0, 50.0);
+        let uniforms = WindowUniforms::place(WINDOW, wide, true, 1.0, FRAC_PI_2);
+        assert_corners(uniforms, [[125.0, 0.0], [75.0, 100.0]]);
+    }
+
+    #[test]
+    fn stretches_over_the_whole_window() {
+        let small = Vec2::new(10.0, 10.0);
+        let uniforms = WindowUniforms::place(WINDOW, small, false, 1.0, 0.0);
+        assert_corners(uniforms, [[0.0, 0.0], [200.0, 100.0]]);
+
+        let uniforms = WindowUniforms::place(WINDOW, small, false, 1.0, FRAC_PI_2);
+        assert_corners(uniforms, [[200.0, 0.0], [0.0, 100.0]]);
+    }
+
+    #[test]
+    fn zooms_around_the_centre() {
+        let square = Vec2::new(100.0, 100.0);
+        let uniforms = WindowUniforms::place(WINDOW, square, true, 2.0, 0.0);
+        assert_corners(uniforms, [[0.0, -50.0], [200.0, 150.0]]);
+    }
+}
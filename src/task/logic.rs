@@ -1,7 +1,11 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use tokio::sync::broadcast;
 use tokio::sync::mpsc;
 use tokio::sync::oneshot;
 
-use crate::logic::msg::EventHandler;
+use crate::logic::msg::{Event, EventHandler, Launch, Request, TerminalMsg};
+use crate::terminal::{Terminal, TerminalError};
 
 use crate::prelude::*;
 
@@ -18,47 +22,291 @@ impl Context for LogicalError {}
 
 /// Main logic task and root of tokio runtime.
 ///
-/// takes a proxy to the event loop and an interupt handle.
-/// when any data is sent on the handle the programe exits
+/// takes a proxy to the event loop, an interupt handle, both ends of the
+/// request channel, what to open from the command line, the socket to listen
+/// on (if any), the window events sent by the render thread and the terminal
+/// to draw requests for it in. when any data is sent on the handle the
+/// programe exits
+#[allow(clippy::too_many_arguments)]
 pub async fn logic(
     elp: crate::ImvrEventLoopProxy,
-    mut cls: oneshot::Receiver<()>,
+    cls: oneshot::Receiver<()>,
+    tx: mpsc::Sender<Request>,
+    mut rx: mpsc::Receiver<Request>,
+    launch: Launch,
+    socket: Option<PathBuf>,
+    events: broadcast::Sender<Event>,
+    mut terminal: Option<Terminal<io::Stdout>>,
 ) -> Result<(), LogicalError> {
-    let (tx, mut rx) = mpsc::channel(4);
-
     // spawns the tasks
-    let mut handlrs = EventHandler::spawn(tx);
+    let mut handlrs = EventHandler::spawn(tx, launch, socket, events);
+
+    dispatch(&mut rx, cls, &mut terminal, |msg| {
+        elp.send_event(msg)
+            .attach_printable("Failed to send request to render thread.")
+            .change_context(LogicalError)
+    })
+    .await?;
+
+    rx.close();
+    handlrs.close().await.change_context(LogicalError)?;
 
+    Ok(())
+}
+
+/// Passes requests on to where they are handled until `cls` fires (or is
+/// dropped) or every source of requests hangs up.
+///
+/// Requests for the terminal are handled here since it is not tied to the
+/// event loop, and fail if there is no terminal.
+async fn dispatch(
+    rx: &mut mpsc::Receiver<Request>,
+    mut cls: oneshot::Receiver<()>,
+    terminal: &mut Option<Terminal<impl Write>>,
+    mut send: impl FnMut(WindowMsg) -> Result<(), LogicalError>,
+) -> Result<(), LogicalError> {
     loop {
-        // this cant be done with `select` beacuse oneshot's future takes 
-        // ownership
-
-        use tokio::sync::mpsc::error::TryRecvError as MTRE;
-        match rx.try_recv() {
-            Ok(mut msg) => {
-                if let Some(msg) = msg.as_window() {
-                    elp.send_event(msg)
-                        .attach_printable("Failed to send request to render thread.")
-                        .change_context(LogicalError)?;
-                }
+        tokio::select! {
+            _ = &mut cls => break,
+            req = rx.recv() => {
+                let Some(mut req) = req else { break };
 
-                if let Some(_msg) = msg.as_terminal() {
-                    // log::warn!("send {msg:?} to terminal")
+                if let Some(msg) = req.as_terminal() {
+                    show_in_terminal(terminal, msg);
+                } else if let Some(msg) = req.as_window() {
+                    send(msg)?;
                 }
             }
-            Err(MTRE::Disconnected) => break,
-            Err(MTRE::Empty) => {}
         }
+    }
 
-        use tokio::sync::oneshot::error::TryRecvError as OTRE;
-        match cls.try_recv() {
-            Ok(_) | Err(OTRE::Closed) => break,
-            Err(OTRE::Empty) => {}
+    Ok(())
+}
+
+fn show_in_terminal(terminal: &mut Option<Terminal<impl Write>>, msg: TerminalMsg) {
+    let detached = || Err(Report::new(TerminalError::Detached));
+    let (res, resp) = match msg {
+        TerminalMsg::ShowImage { image, place, resp } => {
+            let image = match image {
+                Ok(image) => image,
+                Err(e) => {
+                    log::warn!("{e}");
+                    if let Some(resp) = resp {
+                        let _ = resp.fail(e);
+                    }
+                    return;
+                }
+            };
+            let res = match terminal {
+                Some(terminal) => terminal.show(&image, &place),
+                None => detached(),
+            };
+            (res, resp)
+        }
+        TerminalMsg::Clear { resp } => {
+            let res = match terminal {
+                Some(terminal) => terminal.clear(),
+                None => detached(),
+            };
+            (res, resp)
         }
+    };
+
+    if let Err(e) = &res {
+        log::warn!("{e:?}");
+    }
+    if let Some(resp) = resp {
+        let _ = resp.finish(&res);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::logic::msg::{Msg, ReturnAddress};
+    use crate::logic::SurfaceId;
+    use crate::render::gpu::image::PixelFormat;
+    use crate::terminal::Protocol;
+    use std::time::Duration;
+    use tokio::time::timeout;
+
+    const PROMPTLY: Duration = Duration::from_secs(1);
+
+    fn sink() -> Option<Terminal<io::Sink>> {
+        Some(Terminal::new(io::sink(), Protocol::Kitty, false))
     }
 
-    rx.close();
-    handlrs.close().await.change_context(LogicalError)?;
+    #[tokio::test]
+    async fn exits_when_closed() {
+        let (_tx, mut rx) = mpsc::channel::<Request>(4);
+        let (close, cls) = oneshot::channel();
+        close.send(()).unwrap();
 
-    Ok(())
+        let res = timeout(PROMPTLY, dispatch(&mut rx, cls, &mut sink(), |_| Ok(()))).await;
+        assert!(matches!(res, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn exits_when_close_handle_dropped() {
+        let (_tx, mut rx) = mpsc::channel::<Request>(4);
+        let (close, cls) = oneshot::channel::<()>();
+        drop(close);
+
+        let res = timeout(PROMPTLY, dispatch(&mut rx, cls, &mut sink(), |_| Ok(()))).await;
+        assert!(matches!(res, Ok(Ok(()))));
+    }
+
+    #[tokio::test]
+    async fn exits_when_sources_hang_up() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (_close, cls) = oneshot::channel::<()>();
+
+        tx.send(Request::new(Msg::CloseWindow {
+            id: SurfaceId::Window(1),
+        }))
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut sent = Vec::new();
+        let res = timeout(
+            PROMPTLY,
+            dispatch(&mut rx, cls, &mut sink(), |msg| {
+                sent.push(msg);
+                Ok(())
+            }),
+        )
+        .await;
+
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(matches!(sent[..], [WindowMsg::CloseWindow { .. }]));
+    }
+
+    #[tokio::test]
+    async fn terminal_requests_do_not_reach_windows() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (_close, cls) = oneshot::channel::<()>();
+        let (otx, orx) = oneshot::channel();
+
+        tx.send(Request {
+            msg: Msg::ShowImageRaw {
+                width: 1,
+                height: 1,
+                format: PixelFormat::Rgb8,
+                stride: None,
+                data: vec![1, 2, 3],
+                id: SurfaceId::Terminal,
+                placement: None,
+            },
+            resp: Some(ReturnAddress::Done(otx)),
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut terminal = Some(Terminal::new(Vec::new(), Protocol::Kitty, false));
+        let mut sent = Vec::new();
+        let res = timeout(
+            PROMPTLY,
+            dispatch(&mut rx, cls, &mut terminal, |msg| {
+                sent.push(msg);
+                Ok(())
+            }),
+        )
+        .await;
+
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(sent.is_empty());
+        assert_eq!(orx.await.unwrap(), Ok(()));
+        assert_eq!(
+            terminal.unwrap().into_inner(),
+            b"\x1b_Ga=T,f=24,s=1,v=1,i=1,q=2,m=0;AQID\x1b\\"
+        );
+    }
+
+    #[tokio::test]
+    async fn terminal_requests_are_placed_where_asked() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (_close, cls) = oneshot::channel::<()>();
+
+        let msg = json::from_str(
+            r#"{"ShowImageRaw":{"width":1,"height":1,"format":"Mono8","data":"AA==",
+                "id":"Terminal","placement":{"at":[4,2],"columns":10}}}"#,
+        )
+        .unwrap();
+        tx.send(Request::new(msg)).await.unwrap();
+        drop(tx);
+
+        let mut terminal = Some(Terminal::new(Vec::new(), Protocol::Kitty, false));
+        let res = timeout(PROMPTLY, dispatch(&mut rx, cls, &mut terminal, |_| Ok(()))).await;
+
+        assert!(matches!(res, Ok(Ok(()))));
+        assert_eq!(
+            terminal.unwrap().into_inner(),
+            b"\x1b7\x1b[3;5H\x1b_Ga=T,f=24,s=1,v=1,i=1,q=2,c=10,C=1,m=0;AAAA\x1b\\\x1b8"
+        );
+    }
+
+    #[tokio::test]
+    async fn terminal_requests_fail_without_a_terminal() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (_close, cls) = oneshot::channel::<()>();
+        let (otx, orx) = oneshot::channel();
+
+        tx.send(Request {
+            msg: Msg::CloseWindow {
+                id: SurfaceId::Terminal,
+            },
+            resp: Some(ReturnAddress::Done(otx)),
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut detached: Option<Terminal<io::Sink>> = None;
+        let res = timeout(PROMPTLY, dispatch(&mut rx, cls, &mut detached, |_| Ok(()))).await;
+
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(orx.await.unwrap().is_err());
+    }
+
+    #[tokio::test]
+    async fn terminal_images_that_fail_to_load_do_not_reach_windows() {
+        let (tx, mut rx) = mpsc::channel(4);
+        let (_close, cls) = oneshot::channel::<()>();
+        let (otx, orx) = oneshot::channel();
+
+        tx.send(Request {
+            msg: Msg::ShowImageRaw {
+                width: 2,
+                height: 2,
+                format: PixelFormat::Rgb8,
+                stride: None,
+                data: vec![1, 2, 3],
+                id: SurfaceId::Terminal,
+                placement: None,
+            },
+            resp: Some(ReturnAddress::Done(otx)),
+        })
+        .await
+        .unwrap();
+        drop(tx);
+
+        let mut terminal = Some(Terminal::new(Vec::new(), Protocol::Kitty, false));
+        let mut sent = Vec::new();
+        let res = timeout(
+            PROMPTLY,
+            dispatch(&mut rx, cls, &mut terminal, |msg| {
+                sent.push(msg);
+                Ok(())
+            }),
+        )
+        .await;
+
+        assert!(matches!(res, Ok(Ok(()))));
+        assert!(sent.is_empty());
+        let err = orx.await.unwrap().unwrap_err();
+        assert!(err.contains("not enough data"), "{err}");
+        assert!(terminal.unwrap().into_inner().is_empty());
+    }
 }
@@ -1,6 +1,11 @@
 use crate::prelude::*;
 
+use crate::logic::msg::{Event, Request};
 use crate::ImvrEventLoop;
+use tokio::sync::{broadcast, mpsc};
+use winit::event::WindowEvent;
+use winit::event_loop::ControlFlow;
+use winit::keyboard::ModifiersState;
 
 #[derive(Debug)]
 pub struct WindowError;
@@ -12,15 +17,38 @@ impl fmt::Display for WindowError {
 }
 impl Context for WindowError {}
 
-pub fn window(eloop: ImvrEventLoop) -> Result<(), WindowError> {
-    let mut context = GlobalContext::new();
+pub fn window(
+    eloop: ImvrEventLoop,
+    events: broadcast::Sender<Event>,
+    persist: bool,
+    requests: mpsc::Sender<Request>,
+) -> Result<(), WindowError> {
+    let mut context = GlobalContext::new(events, persist, requests);
+
+    // only wake up for events so an idle server with no windows costs nothing
+    eloop.set_control_flow(ControlFlow::Wait);
+
+    // winit tells of modifiers apart from the keys they are held with
+    let mut modifiers = ModifiersState::empty();
 
     let res = eloop.run(move |evnt, elwt| {
         if let winit::event::Event::UserEvent(ref e) = evnt {
             log::info!("user event: {:?}", &e);
         }
 
-        let Some(msg) = evnt.some_into() else { return };
+        context.emit(&evnt);
+
+        if let winit::event::Event::WindowEvent {
+            event: WindowEvent::ModifiersChanged(changed),
+            ..
+        } = &evnt
+        {
+            modifiers = changed.state();
+        }
+
+        let Some(msg) = (evnt, modifiers).some_into() else {
+            return;
+        };
 
         let res = context.handle(msg, elwt);
 
@@ -0,0 +1,210 @@
+//! The kitty graphics protocol.
+//!
+//! Images are sent as base64 pixel data split over escape sequences of at
+//! most [`CHUNK`] characters each. See
+//! <https://sw.kovidgoyal.net/kitty/graphics-protocol/>.
+
+use std::io::{self, Write};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use super::{has_alpha, packed, Placement};
+use crate::util::RawImage;
+
+/// The most base64 a single escape sequence carries.
+pub const CHUNK: usize = 4096;
+
+/// Images shown in a terminal through the kitty protocol.
+#[derive(Debug)]
+pub struct Kitty {
+    next_id: u32,
+    shown: Vec<u32>,
+}
+
+impl Default for Kitty {
+    fn default() -> Self {
+        Self {
+            next_id: 1,
+            shown: Vec::new(),
+        }
+    }
+}
+
+impl Kitty {
+    /// Sends `image` to be shown at `place`, returning the id the terminal
+    /// knows it by.
+    pub fn show(
+        &mut self,
+        out: &mut impl Write,
+        image: &RawImage,
+        place: &Placement,
+    ) -> io::Result<u32> {
+        let id = self.next_id;
+        // 0 means the terminal picks the id, which would lose track of it
+        self.next_id = self.next_id.checked_add(1).unwrap_or(1);
+
+        transmit(out, id, image, place)?;
+        self.shown.push(id);
+        Ok(id)
+    }
+
+    /// Removes every image this has shown.
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        for id in std::mem::take(&mut self.shown) {
+            write!(out, "\x1b_Ga=d,d=I,i={id},q=2\x1b\\")?;
+        }
+        out.flush()
+    }
+}
+
+/// Writes the escape sequences that transmit and place `image` in one go.
+fn transmit(out: &mut impl Write, id: u32, image: &RawImage, place: &Placement) -> io::Result<()> {
+    let alpha = has_alpha(image.info.pixel_format);
+    let payload = STANDARD.encode(packed(image, alpha));
+    let (width, height) = (image.info.size.x, image.info.size.y);
+
+    let mut control = format!(
+        "a=T,f={},s={width},v={height},i={id},q=2",
+        if alpha { 32 } else { 24 }
+    );
+    if let Some(columns) = place.columns {
+        control += &format!(",c={columns}");
+    }
+    if let Some(rows) = place.rows {
+        control += &format!(",r={rows}");
+    }
+
+    if let Some((column, row)) = place.at {
+        // the cursor is put back afterwards so placing an image does not
+        // move whatever is being typed
+        write!(
+            out,
+            "\x1b7\x1b[{};{}H",
+            u32::from(row) + 1,
+            u32::from(column) + 1
+        )?;
+        control += ",C=1";
+    }
+
+    // base64 is ascii so splitting on any byte is splitting on a character
+    let mut chunks = payload.as_bytes().chunks(CHUNK).peekable();
+    let first = chunks.next().unwrap_or_default();
+    let more = u8::from(chunks.peek().is_some());
+    out.write_all(format!("\x1b_G{control},m={more};").as_bytes())?;
+    out.write_all(first)?;
+    out.write_all(b"\x1b\\")?;
+
+    while let Some(chunk) = chunks.next() {
+        let more = u8::from(chunks.peek().is_some());
+        write!(out, "\x1b_Gm={more};")?;
+        out.write_all(chunk)?;
+        out.write_all(b"\x1b\\")?;
+    }
+
+    if place.at.is_some() {
+        out.write_all(b"\x1b8")?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::gpu::image::{Alpha, PixelFormat};
+
+    fn show(image: &RawImage, place: &Placement) -> String {
+        let mut out = Vec::new();
+        Kitty::default().show(&mut out, image, place).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn sends_small_images_in_one_sequence() {
+        let image =
+            RawImage::from_raw(PixelFormat::Bgr8, 2, 1, Box::new([3, 2, 1, 6, 5, 4])).unwrap();
+        assert_eq!(
+            show(&image, &Placement::default()),
+            "\x1b_Ga=T,f=24,s=2,v=1,i=1,q=2,m=0;AQIDBAUG\x1b\\"
+        );
+
+        let image = RawImage::from_raw(
+            PixelFormat::Rgba8(Alpha::Unpremultiplied),
+            1,
+            1,
+            Box::new([255, 0, 0, 128]),
+        )
+        .unwrap();
+        assert_eq!(
+            show(&image, &Placement::default()),
+            "\x1b_Ga=T,f=32,s=1,v=1,i=1,q=2,m=0;/wAAgA==\x1b\\"
+        );
+    }
+
+    #[test]
+    fn splits_large_images_into_chunks() {
+        // 64 * 64 grey pixels are 12288 bytes of rgb, 16384 of base64
+        let pixels: Vec<u8> = (0..64 * 64).map(|i| i as u8).collect();
+        let image = RawImage::from_raw(PixelFormat::Mono8, 64, 64, pixels.into()).unwrap();
+        let out = show(&image, &Placement::default());
+
+        let sequences: Vec<&str> = out
+            .strip_suffix("\x1b\\")
+            .unwrap()
+            .split("\x1b\\")
+            .collect();
+        assert_eq!(sequences.len(), 4);
+        assert!(sequences[0].starts_with("\x1b_Ga=T,f=24,s=64,v=64,i=1,q=2,m=1;"));
+        assert!(sequences[1].starts_with("\x1b_Gm=1;"));
+        assert!(sequences[2].starts_with("\x1b_Gm=1;"));
+        assert!(sequences[3].starts_with("\x1b_Gm=0;"));
+
+        let mut payload = String::new();
+        for sequence in sequences {
+            let (_, chunk) = sequence.split_once(';').unwrap();
+            assert!(chunk.len() <= CHUNK);
+            payload += chunk;
+        }
+        assert_eq!(STANDARD.decode(payload).unwrap(), packed(&image, false));
+    }
+
+    #[test]
+    fn places_in_a_rectangle_without_moving_the_cursor() {
+        let image = RawImage::from_raw(PixelFormat::Mono8, 1, 1, Box::new([0])).unwrap();
+        let place = Placement {
+            at: Some((4, 2)),
+            columns: Some(10),
+            rows: Some(5),
+        };
+        assert_eq!(
+            show(&image, &place),
+            "\x1b7\x1b[3;5H\x1b_Ga=T,f=24,s=1,v=1,i=1,q=2,c=10,r=5,C=1,m=0;AAAA\x1b\\\x1b8"
+        );
+    }
+
+    #[test]
+    fn clear_deletes_every_image_shown() {
+        let image = RawImage::from_raw(PixelFormat::Mono8, 1, 1, Box::new([0])).unwrap();
+        let mut kitty = Kitty::default();
+        let mut out = Vec::new();
+        assert_eq!(
+            kitty.show(&mut out, &image, &Placement::default()).unwrap(),
+            1
+        );
+        assert_eq!(
+            kitty.show(&mut out, &image, &Placement::default()).unwrap(),
+            2
+        );
+
+        let mut out = Vec::new();
+        kitty.clear(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b_Ga=d,d=I,i=1,q=2\x1b\\\x1b_Ga=d,d=I,i=2,q=2\x1b\\"
+        );
+
+        let mut out = Vec::new();
+        kitty.clear(&mut out).unwrap();
+        assert!(out.is_empty());
+    }
+}
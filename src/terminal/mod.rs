@@ -0,0 +1,349 @@
+//! Showing images in the terminal itself instead of in a window.
+//!
+//! Used both for requests sent to [`SurfaceId::Terminal`] and for `--terminal`
+//! on the command line, which prints images without starting a server.
+//!
+//! [`SurfaceId::Terminal`]: crate::logic::SurfaceId::Terminal
+
+pub mod kitty;
+pub mod sixel;
+
+use serde::{Deserialize, Serialize};
+use std::io::{self, Write};
+
+use crate::logic::msg::Launch;
+use crate::prelude::*;
+use crate::render::gpu::image::{Alpha, PixelFormat};
+use crate::util::RawImage;
+
+use self::kitty::Kitty;
+use self::sixel::Sixel;
+
+#[derive(Debug)]
+pub enum TerminalError {
+    Write,
+    /// There is no terminal to draw in, such as for a server started in the
+    /// background whose output goes to a log.
+    Detached,
+}
+
+impl fmt::Display for TerminalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerminalError::Write => f.write_str("failed to write to the terminal"),
+            TerminalError::Detached => f.write_str("not running in a terminal"),
+        }
+    }
+}
+impl Context for TerminalError {}
+
+/// How images are drawn in a terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Protocol {
+    /// The kitty graphics protocol, also spoken by Ghostty and Konsole.
+    Kitty,
+    /// Sixel graphics, spoken by foot, mlterm, WezTerm and xterm among others.
+    Sixel,
+}
+
+impl Protocol {
+    /// Guesses what this terminal speaks from its environment, falling back to
+    /// kitty.
+    pub fn detect() -> Self {
+        Self::guess(|name| std::env::var(name).ok())
+    }
+
+    fn guess(var: impl Fn(&str) -> Option<String>) -> Self {
+        let term = var("TERM").unwrap_or_default();
+        let program = var("TERM_PROGRAM").unwrap_or_default();
+
+        if var("KITTY_WINDOW_ID").is_some() || term.contains("kitty") || term.contains("ghostty") {
+            return Protocol::Kitty;
+        }
+        // wezterm speaks both but draws sixel more faithfully
+        let sixel = ["foot", "mlterm", "contour", "yaft"];
+        if program == "WezTerm" || sixel.iter().any(|name| term.starts_with(name)) {
+            return Protocol::Sixel;
+        }
+        Protocol::Kitty
+    }
+}
+
+/// Where an image goes on the terminal, in character cells.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Placement {
+    /// The column and row of the top left corner, counting from 0. The image
+    /// is put at the cursor if this is not given.
+    pub at: Option<(u16, u16)>,
+    /// How many columns the image is scaled to span.
+    pub columns: Option<u16>,
+    /// How many rows the image is scaled to span.
+    ///
+    /// With only one of `columns` and `rows` the other follows from the
+    /// aspect ratio, with neither the image is shown at its own size.
+    pub rows: Option<u16>,
+}
+
+/// A terminal that images are shown in, keeping track of them so they can be
+/// removed again.
+pub struct Terminal<W> {
+    out: W,
+    backend: Backend,
+}
+
+enum Backend {
+    Kitty(Kitty),
+    Sixel(Sixel),
+}
+
+impl<W: Write> Terminal<W> {
+    /// Draws with `protocol`, dithering images if it only has a few colours
+    /// to draw with and `dither` is set.
+    pub fn new(out: W, protocol: Protocol, dither: bool) -> Self {
+        let backend = match protocol {
+            Protocol::Kitty => Backend::Kitty(Kitty::default()),
+            Protocol::Sixel => {
+                let limit = pixel_size().unwrap_or((sixel::MAX_SIZE, sixel::MAX_SIZE));
+                Backend::Sixel(Sixel::new(dither, cell_size(), limit))
+            }
+        };
+        Self { out, backend }
+    }
+
+    /// Shows `image` at `place`.
+    pub fn show(&mut self, image: &RawImage, place: &Placement) -> Result<(), TerminalError> {
+        let res = match &mut self.backend {
+            Backend::Kitty(kitty) => kitty.show(&mut self.out, image, place).map(|_| ()),
+            Backend::Sixel(sixel) => sixel.show(&mut self.out, image, place),
+        };
+        res.change_context(TerminalError::Write)
+    }
+
+    /// Removes every image this has shown.
+    pub fn clear(&mut self) -> Result<(), TerminalError> {
+        let res = match &mut self.backend {
+            Backend::Kitty(kitty) => kitty.clear(&mut self.out),
+            Backend::Sixel(sixel) => sixel.clear(&mut self.out),
+        };
+        res.change_context(TerminalError::Write)
+    }
+
+    /// Gets back what was written to.
+    #[cfg(test)]
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+/// Shows everything asked for on the command line in this terminal, one image
+/// under the other.
+///
+/// The size and position from `--geometry` are counted in cells. Without a
+/// size, images wider than the terminal are scaled down to fit it.
+pub fn print(launch: &Launch, protocol: Protocol, dither: bool) -> Result<(), TerminalError> {
+    let mut term = Terminal::new(io::stdout().lock(), protocol, dither);
+
+    let mut images = Vec::new();
+    if let Some(data) = &launch.stdin {
+        let image = image::load_from_memory(data).map_err(|e| e.to_string());
+        match image.and_then(RawImage::try_from) {
+            Ok(image) => images.push(image),
+            Err(e) => log::warn!("failed to decode image from stdin: {e}"),
+        }
+    }
+    let files = launch.files.iter().filter_map(|path| {
+        let image = image::open(path).map_err(|e| e.to_string());
+        match image.and_then(RawImage::try_from) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                log::warn!("failed to open image {path:?}: {e}");
+                None
+            }
+        }
+    });
+
+    for image in images.into_iter().chain(files) {
+        let place = placement(launch, &image);
+        term.show(&image, &place)?;
+        if place.at.is_none() {
+            term.out
+                .write_all(b"\n")
+                .change_context(TerminalError::Write)?;
+        }
+    }
+    term.out.flush().change_context(TerminalError::Write)
+}
+
+/// Where `image` goes given the geometry asked for.
+fn placement(launch: &Launch, image: &RawImage) -> Placement {
+    let clamp = |n: i64| n.clamp(0, u16::MAX.into()) as u16;
+    let props = &launch.props;
+
+    let mut place = Placement {
+        at: props
+            .position
+            .map(|p| (clamp(p.x.into()), clamp(p.y.into()))),
+        columns: props.size.map(|s| clamp(s.width.into())),
+        rows: props.size.map(|s| clamp(s.height.into())),
+    };
+
+    if props.size.is_none() {
+        if let Ok(size) = crossterm::terminal::window_size() {
+            if size.width > 0 && image.info.size.x > u32::from(size.width) {
+                place.columns = Some(size.columns);
+            }
+        }
+    }
+    place
+}
+
+/// The width and height of a cell in pixels, guessing if the terminal does
+/// not say.
+pub(crate) fn cell_size() -> (u32, u32) {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 && size.columns > 0 && size.rows > 0 => (
+            u32::from(size.width / size.columns),
+            u32::from(size.height / size.rows),
+        ),
+        _ => (10, 20),
+    }
+}
+
+/// The width and height of the terminal in pixels, if it says.
+fn pixel_size() -> Option<(u32, u32)> {
+    match crossterm::terminal::window_size() {
+        Ok(size) if size.width > 0 && size.height > 0 => {
+            Some((size.width.into(), size.height.into()))
+        }
+        _ => None,
+    }
+}
+
+/// Whether pixels in `format` carry alpha.
+pub fn has_alpha(format: PixelFormat) -> bool {
+    matches!(
+        format,
+        PixelFormat::MonoAlpha8(_) | PixelFormat::Bgra8(_) | PixelFormat::Rgba8(_)
+    )
+}
+
+/// The pixels of `image` as tightly packed 8-bit RGB, or RGBA with straight
+/// alpha if `alpha`.
+pub fn packed(image: &RawImage, alpha: bool) -> Vec<u8> {
+    let info = image.info;
+    let (width, height) = (info.size.x as usize, info.size.y as usize);
+    let (step, channels) = (info.stride.x as usize, if alpha { 4 } else { 3 });
+
+    let mut out = Vec::with_capacity(width * height * channels);
+    for y in 0..height {
+        let row = &image.data[y * info.stride.y as usize..];
+        for x in 0..width {
+            let pixel = rgba(info.pixel_format, &row[x * step..][..step]);
+            out.extend_from_slice(&pixel[..channels]);
+        }
+    }
+    out
+}
+
+/// Reads the pixel in `px` as RGBA with straight alpha.
+fn rgba(format: PixelFormat, px: &[u8]) -> [u8; 4] {
+    let (pixel, alpha) = match format {
+        PixelFormat::Mono8 => ([px[0], px[0], px[0], 255], None),
+        // only laid out with its alpha when the stride leaves room for it
+        PixelFormat::MonoAlpha8(a) => {
+            let alpha = px.get(1).copied().unwrap_or(255);
+            ([px[0], px[0], px[0], alpha], Some(a))
+        }
+        PixelFormat::Bgr8 => ([px[2], px[1], px[0], 255], None),
+        PixelFormat::Bgra8(a) => ([px[2], px[1], px[0], px[3]], Some(a)),
+        PixelFormat::Rgb8 => ([px[0], px[1], px[2], 255], None),
+        PixelFormat::Rgba8(a) => ([px[0], px[1], px[2], px[3]], Some(a)),
+    };
+
+    match (alpha, pixel[3]) {
+        (Some(Alpha::Premultiplied), a) if a > 0 && a < 255 => {
+            let straight =
+                |c: u8| ((u16::from(c) * 255 + u16::from(a) / 2) / u16::from(a)).min(255) as u8;
+            [
+                straight(pixel[0]),
+                straight(pixel[1]),
+                straight(pixel[2]),
+                a,
+            ]
+        }
+        _ => pixel,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn packs_every_format_as_rgb() {
+        let bgra = RawImage::from_raw(
+            PixelFormat::Bgra8(Alpha::Premultiplied),
+            2,
+            1,
+            Box::new([30, 20, 10, 255, 0, 0, 64, 128]),
+        )
+        .unwrap();
+        assert_eq!(packed(&bgra, true), [10, 20, 30, 255, 128, 0, 0, 128]);
+        assert_eq!(packed(&bgra, false), [10, 20, 30, 128, 0, 0]);
+
+        let mono = RawImage::from_raw(PixelFormat::Mono8, 1, 2, Box::new([7, 9])).unwrap();
+        assert_eq!(packed(&mono, false), [7, 7, 7, 9, 9, 9]);
+    }
+
+    #[test]
+    fn guesses_the_protocol_from_the_environment() {
+        let env = |vars: &'static [(&'static str, &'static str)]| {
+            move |name: &str| {
+                let var = vars.iter().find(|(var, _)| *var == name);
+                var.map(|(_, value)| value.to_string())
+            }
+        };
+
+        assert_eq!(
+            Protocol::guess(env(&[("TERM", "xterm-kitty")])),
+            Protocol::Kitty
+        );
+        assert_eq!(
+            Protocol::guess(env(&[("TERM", "foot-extra")])),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            Protocol::guess(env(&[
+                ("TERM", "xterm-256color"),
+                ("TERM_PROGRAM", "WezTerm")
+            ])),
+            Protocol::Sixel
+        );
+        assert_eq!(
+            Protocol::guess(env(&[("TERM", "foot"), ("KITTY_WINDOW_ID", "1")])),
+            Protocol::Kitty
+        );
+        assert_eq!(Protocol::guess(env(&[])), Protocol::Kitty);
+    }
+
+    #[test]
+    fn decoded_images_keep_their_alpha() {
+        let rgba = image::RgbaImage::from_raw(1, 1, vec![1, 2, 3, 4]).unwrap();
+        let image = RawImage::try_from(image::DynamicImage::ImageRgba8(rgba)).unwrap();
+        assert!(has_alpha(image.info.pixel_format));
+        assert_eq!(packed(&image, true), [1, 2, 3, 4]);
+
+        let rgb = image::RgbImage::from_raw(1, 1, vec![1, 2, 3]).unwrap();
+        let image = RawImage::try_from(image::DynamicImage::ImageRgb8(rgb)).unwrap();
+        assert_eq!(image.info.pixel_format, PixelFormat::Rgb8);
+    }
+
+    #[test]
+    fn skips_row_padding() {
+        let mut info = crate::render::gpu::image::ImageInfo::new(PixelFormat::Rgb8, 1, 2).unwrap();
+        info.stride.y = 4;
+        let image = RawImage::new(info, vec![1u8, 2, 3, 0, 4, 5, 6, 0].into_boxed_slice()).unwrap();
+        assert_eq!(packed(&image, false), [1, 2, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,477 @@
+//! Sixel graphics, for terminals that do not speak the kitty protocol.
+//!
+//! Sixel draws from a palette of at most [`MAX_COLORS`] colours, so images are
+//! quantized by median cut first, optionally with Floyd-Steinberg dithering.
+//! The terminal does not scale them either, so images are resized here to
+//! cover the cells asked for, though never past the size of the terminal.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use super::{packed, Placement};
+use crate::util::RawImage;
+
+/// The most colours a palette holds.
+pub const MAX_COLORS: usize = 256;
+
+/// Alpha below this is left transparent, sixel has nothing in between.
+const OPAQUE: u8 = 128;
+
+/// The largest width and height drawn in a terminal that does not say how
+/// large it is.
+pub const MAX_SIZE: u32 = 4096;
+
+/// Images shown in a terminal as sixels.
+#[derive(Debug)]
+pub struct Sixel {
+    dither: bool,
+    /// The width and height of a cell in pixels.
+    cell: (u32, u32),
+    /// The largest width and height an image is drawn at.
+    limit: (u32, u32),
+    /// The column, row, columns and rows covered by each image placed at a
+    /// position.
+    placed: Vec<[u32; 4]>,
+}
+
+impl Sixel {
+    /// Draws with cells of `cell` pixels, scaling images down to fit in
+    /// `limit` pixels.
+    pub fn new(dither: bool, cell: (u32, u32), limit: (u32, u32)) -> Self {
+        Self {
+            dither,
+            cell: (cell.0.max(1), cell.1.max(1)),
+            limit: (limit.0.max(1), limit.1.max(1)),
+            placed: Vec::new(),
+        }
+    }
+
+    /// Draws `image` at `place`, scaled to the cells it asks for.
+    pub fn show(
+        &mut self,
+        out: &mut impl Write,
+        image: &RawImage,
+        place: &Placement,
+    ) -> io::Result<()> {
+        let from = (image.info.size.x, image.info.size.y);
+        if from.0 == 0 || from.1 == 0 {
+            return Ok(());
+        }
+
+        let to = self.size(from, place);
+        let mut rgba = packed(image, true);
+        if to != from {
+            rgba = scale(rgba, from, to);
+        }
+
+        if let Some((column, row)) = place.at {
+            write!(
+                out,
+                "\x1b7\x1b[{};{}H",
+                u32::from(row) + 1,
+                u32::from(column) + 1
+            )?;
+        }
+        encode(out, &rgba, to.0 as usize, to.1 as usize, self.dither)?;
+        if let Some((column, row)) = place.at {
+            out.write_all(b"\x1b8")?;
+            let (columns, rows) = (to.0.div_ceil(self.cell.0), to.1.div_ceil(self.cell.1));
+            self.placed.push([column.into(), row.into(), columns, rows]);
+        }
+        out.flush()
+    }
+
+    /// Blanks the cells under every image placed at a position.
+    ///
+    /// Images drawn at the cursor are part of the scrollback like any other
+    /// output and are left alone.
+    pub fn clear(&mut self, out: &mut impl Write) -> io::Result<()> {
+        for [column, row, columns, rows] in std::mem::take(&mut self.placed) {
+            out.write_all(b"\x1b7")?;
+            for row in row..row + rows {
+                write!(out, "\x1b[{};{}H\x1b[{columns}X", row + 1, column + 1)?;
+            }
+            out.write_all(b"\x1b8")?;
+        }
+        out.flush()
+    }
+
+    /// The size in pixels to draw an image of size `from` at, keeping its
+    /// aspect ratio when it has to be shrunk to fit the limit.
+    fn size(&self, from: (u32, u32), place: &Placement) -> (u32, u32) {
+        let (width, height) = (u64::from(from.0), u64::from(from.1));
+        let (cell_w, cell_h) = (u64::from(self.cell.0), u64::from(self.cell.1));
+
+        let (w, h) = match (place.columns, place.rows) {
+            (Some(columns), Some(rows)) => (u64::from(columns) * cell_w, u64::from(rows) * cell_h),
+            (Some(columns), None) => {
+                let w = u64::from(columns) * cell_w;
+                (w, height * w / width)
+            }
+            (None, Some(rows)) => {
+                let h = u64::from(rows) * cell_h;
+                (width * h / height, h)
+            }
+            (None, None) => (width, height),
+        };
+        let clamp = |n: u64| n.clamp(1, u32::MAX.into());
+        let (w, h) = (clamp(w), clamp(h));
+
+        // both fit in u32 so none of this can overflow
+        let (max_w, max_h) = (u64::from(self.limit.0), u64::from(self.limit.1));
+        let (w, h) = if w <= max_w && h <= max_h {
+            (w, h)
+        } else if w * max_h > h * max_w {
+            (max_w, h * max_w / w)
+        } else {
+            (w * max_h / h, max_h)
+        };
+        (clamp(w) as u32, clamp(h) as u32)
+    }
+}
+
+fn scale(rgba: Vec<u8>, from: (u32, u32), to: (u32, u32)) -> Vec<u8> {
+    let image =
+        image::RgbaImage::from_raw(from.0, from.1, rgba).expect("packed pixels fill the image");
+    image::imageops::resize(&image, to.0, to.1, image::imageops::FilterType::Triangle).into_raw()
+}
+
+/// Writes tightly packed straight RGBA pixels as one sixel image.
+fn encode(
+    out: &mut impl Write,
+    rgba: &[u8],
+    width: usize,
+    height: usize,
+    dither: bool,
+) -> io::Result<()> {
+    let opaque = rgba
+        .chunks_exact(4)
+        .filter(|p| p[3] >= OPAQUE)
+        .map(|p| [p[0], p[1], p[2]]);
+    let palette = median_cut(opaque, MAX_COLORS);
+    let pixels = quantize(rgba, width, &palette, dither);
+
+    // a transparent background, with the size given up front so the
+    // terminal does not have to guess it from the bands
+    write!(out, "\x1bP0;1;0q\"1;1;{width};{height}")?;
+    let percent = |c: u8| (u32::from(c) * 100 + 127) / 255;
+    for (i, [r, g, b]) in palette.iter().enumerate() {
+        write!(
+            out,
+            "#{i};2;{};{};{}",
+            percent(*r),
+            percent(*g),
+            percent(*b)
+        )?;
+    }
+
+    // every band is six rows, drawn once for each colour in it
+    for (band, rows) in pixels.chunks(width * 6).enumerate() {
+        if band > 0 {
+            out.write_all(b"-")?;
+        }
+
+        let mut used = [false; MAX_COLORS];
+        for &i in rows.iter().flatten() {
+            used[usize::from(i)] = true;
+        }
+
+        let colors = (0..palette.len()).filter(|&i| used[i]);
+        for (n, color) in colors.enumerate() {
+            if n > 0 {
+                out.write_all(b"$")?;
+            }
+            let sixels = (0..width).map(|x| {
+                let bits = rows
+                    .chunks_exact(width)
+                    .enumerate()
+                    .filter(|(_, row)| row[x].map(usize::from) == Some(color))
+                    .fold(0, |bits, (y, _)| bits | 1 << y);
+                b'?' + bits
+            });
+            write!(out, "#{color}")?;
+            write_runs(out, sixels)?;
+        }
+    }
+
+    out.write_all(b"\x1b\\")
+}
+
+/// Writes sixels with repeats run length encoded, dropping empty ones at the
+/// end of the row.
+fn write_runs(out: &mut impl Write, sixels: impl Iterator<Item = u8>) -> io::Result<()> {
+    let mut sixels: Vec<u8> = sixels.collect();
+    while sixels.last() == Some(&b'?') {
+        sixels.pop();
+    }
+
+    for run in sixels.chunk_by(|a, b| a == b) {
+        match run.len() {
+            // a repeat is only shorter from four on
+            n @ 1..=3 => out.write_all(&run[..n])?,
+            n => write!(out, "!{n}{}", char::from(run[0]))?,
+        }
+    }
+    Ok(())
+}
+
+/// Picks up to `max` colours to stand in for `pixels` by splitting the
+/// colours they use in halves until there are enough groups, then averaging
+/// each group.
+pub fn median_cut(pixels: impl Iterator<Item = [u8; 3]>, max: usize) -> Vec<[u8; 3]> {
+    let mut histogram = HashMap::<[u8; 3], u64>::new();
+    for pixel in pixels {
+        *histogram.entry(pixel).or_default() += 1;
+    }
+    if histogram.is_empty() || max == 0 {
+        return Vec::new();
+    }
+
+    // sorted so the same image always gets the same palette
+    let mut colors: Vec<_> = histogram.into_iter().collect();
+    colors.sort_unstable();
+
+    let mut boxes = vec![colors];
+    while boxes.len() < max {
+        // the box covering the most pixels over the widest range is split
+        // first, so common colours get finer steps than rare ones
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .max_by_key(|(i, colors)| {
+                let (_, range) = widest_channel(colors);
+                let count: u64 = colors.iter().map(|(_, n)| n).sum();
+                (u64::from(range) * count, std::cmp::Reverse(*i))
+            })
+            .map(|(i, _)| i);
+        let Some(i) = widest else { break };
+
+        let upper = split(&mut boxes[i]);
+        boxes.push(upper);
+    }
+
+    boxes.iter().map(|colors| mean(colors)).collect()
+}
+
+/// The channel the colours differ most in and by how much.
+fn widest_channel(colors: &[([u8; 3], u64)]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let (lo, hi) = colors.iter().fold((u8::MAX, 0), |(lo, hi), (color, _)| {
+                (lo.min(color[c]), hi.max(color[c]))
+            });
+            (c, hi.saturating_sub(lo))
+        })
+        .max_by_key(|&(c, range)| (range, std::cmp::Reverse(c)))
+        .unwrap_or((0, 0))
+}
+
+/// Splits `colors` at the median pixel along their widest channel, leaving
+/// the lower half and returning the upper.
+fn split(colors: &mut Vec<([u8; 3], u64)>) -> Vec<([u8; 3], u64)> {
+    let (channel, _) = widest_channel(colors);
+    colors.sort_by_key(|(color, _)| color[channel]);
+
+    let total: u64 = colors.iter().map(|(_, n)| n).sum();
+    let mut seen = 0;
+    let median = colors
+        .iter()
+        .position(|(_, n)| {
+            seen += n;
+            seen * 2 >= total
+        })
+        .map_or(1, |i| i + 1);
+    colors.split_off(median.clamp(1, colors.len() - 1))
+}
+
+fn mean(colors: &[([u8; 3], u64)]) -> [u8; 3] {
+    let count: u64 = colors.iter().map(|(_, n)| n).sum();
+    let mut sum = [0u64; 3];
+    for (color, n) in colors {
+        for c in 0..3 {
+            sum[c] += u64::from(color[c]) * n;
+        }
+    }
+    sum.map(|s| ((s + count / 2) / count.max(1)) as u8)
+}
+
+/// Maps each pixel to the palette entry closest to it, `None` for transparent
+/// ones.
+fn quantize(rgba: &[u8], width: usize, palette: &[[u8; 3]], dither: bool) -> Vec<Option<u8>> {
+    let mut cache = HashMap::new();
+    let mut lookup = |color: [u8; 3]| -> u8 {
+        *cache
+            .entry(color)
+            .or_insert_with(|| nearest(palette, color))
+    };
+
+    if !dither {
+        return rgba
+            .chunks_exact(4)
+            .map(|p| (p[3] >= OPAQUE).then(|| lookup([p[0], p[1], p[2]])))
+            .collect();
+    }
+
+    // floyd-steinberg, passing what each pixel is off by on to the ones
+    // right of and below it
+    let mut wanted: Vec<[i32; 3]> = rgba
+        .chunks_exact(4)
+        .map(|p| [p[0], p[1], p[2]].map(i32::from))
+        .collect();
+    let height = wanted.len() / width.max(1);
+    let mut pixels = Vec::with_capacity(wanted.len());
+
+    for i in 0..wanted.len() {
+        if rgba[i * 4 + 3] < OPAQUE {
+            pixels.push(None);
+            continue;
+        }
+
+        let color = wanted[i].map(|c| c.clamp(0, 255) as u8);
+        let index = lookup(color);
+        pixels.push(Some(index));
+
+        let chosen = palette[usize::from(index)];
+        let error: [i32; 3] = std::array::from_fn(|c| wanted[i][c] - i32::from(chosen[c]));
+        let (x, y) = (i % width, i / width);
+        let mut spread = |j: usize, weight: i32| {
+            for c in 0..3 {
+                wanted[j][c] += error[c] * weight / 16;
+            }
+        };
+        if x + 1 < width {
+            spread(i + 1, 7);
+        }
+        if y + 1 < height {
+            if x > 0 {
+                spread(i + width - 1, 3);
+            }
+            spread(i + width, 5);
+            if x + 1 < width {
+                spread(i + width + 1, 1);
+            }
+        }
+    }
+    pixels
+}
+
+fn nearest(palette: &[[u8; 3]], color: [u8; 3]) -> u8 {
+    let distance = |entry: &[u8; 3]| -> u32 {
+        (0..3)
+            .map(|c| u32::from(entry[c].abs_diff(color[c])).pow(2))
+            .sum()
+    };
+    (0..palette.len())
+        .min_by_key(|&i| distance(&palette[i]))
+        .unwrap_or(0) as u8
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::render::gpu::image::{Alpha, PixelFormat};
+
+    fn show(sixel: &mut Sixel, image: &RawImage, place: &Placement) -> String {
+        let mut out = Vec::new();
+        sixel.show(&mut out, image, place).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn draws_each_colour_of_a_band() {
+        #[rustfmt::skip]
+        let pixels = [
+            255, 0, 0, 255,   255, 0, 0, 255,
+            0, 0, 255, 255,   0, 0, 0, 0,
+        ];
+        let format = PixelFormat::Rgba8(Alpha::Unpremultiplied);
+        let image = RawImage::from_raw(format, 2, 2, Box::new(pixels)).unwrap();
+
+        assert_eq!(
+            show(
+                &mut Sixel::new(false, (10, 20), (MAX_SIZE, MAX_SIZE)),
+                &image,
+                &Placement::default()
+            ),
+            "\x1bP0;1;0q\"1;1;2;2#0;2;0;0;100#1;2;100;0;0#0A$#1@@\x1b\\"
+        );
+    }
+
+    #[test]
+    fn repeats_are_run_length_encoded() {
+        let image = RawImage::from_raw(PixelFormat::Mono8, 10, 7, Box::new([255; 70])).unwrap();
+        assert_eq!(
+            show(
+                &mut Sixel::new(false, (10, 20), (MAX_SIZE, MAX_SIZE)),
+                &image,
+                &Placement::default()
+            ),
+            "\x1bP0;1;0q\"1;1;10;7#0;2;100;100;100#0!10~-#0!10@\x1b\\"
+        );
+    }
+
+    #[test]
+    fn palette_never_exceeds_its_limit() {
+        let pixels = (0..=255u8).flat_map(|r| [[r, 0, 0], [r, 255, 0]]);
+        let palette = median_cut(pixels, MAX_COLORS);
+        assert_eq!(palette.len(), MAX_COLORS);
+
+        let palette = median_cut([[1, 2, 3], [1, 2, 3], [9, 9, 9]].into_iter(), MAX_COLORS);
+        assert_eq!(palette, [[1, 2, 3], [9, 9, 9]]);
+    }
+
+    #[test]
+    fn dithering_mixes_the_nearest_colours() {
+        let grey = [128, 128, 128, 255].repeat(16);
+        let palette = [[0, 0, 0], [255, 255, 255]];
+
+        let flat = quantize(&grey, 4, &palette, false);
+        assert!(flat.iter().all(|&i| i == Some(1)));
+
+        let dithered = quantize(&grey, 4, &palette, true);
+        let white = dithered.iter().filter(|&&i| i == Some(1)).count();
+        assert!((6..=10).contains(&white), "{dithered:?}");
+    }
+
+    #[test]
+    fn scales_to_the_cells_asked_for() {
+        let image = RawImage::from_raw(PixelFormat::Mono8, 4, 2, Box::new([0; 8])).unwrap();
+        let mut sixel = Sixel::new(false, (8, 16), (MAX_SIZE, MAX_SIZE));
+
+        let place = Placement {
+            at: Some((1, 2)),
+            columns: Some(3),
+            rows: Some(2),
+        };
+        let out = show(&mut sixel, &image, &place);
+        assert!(out.starts_with("\x1b7\x1b[3;2H\x1bP0;1;0q\"1;1;24;32#"));
+        assert!(out.ends_with("\x1b\\\x1b8"));
+
+        let place = Placement {
+            columns: Some(2),
+            ..Placement::default()
+        };
+        assert!(show(&mut sixel, &image, &place).starts_with("\x1bP0;1;0q\"1;1;16;8#"));
+
+        let mut out = Vec::new();
+        sixel.clear(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b7\x1b[3;2H\x1b[3X\x1b[4;2H\x1b[3X\x1b8"
+        );
+    }
+
+    #[test]
+    fn never_draws_past_the_limit() {
+        let sixel = Sixel::new(false, (8, 16), (10, 100));
+
+        let place = Placement {
+            columns: Some(100),
+            ..Placement::default()
+        };
+        assert_eq!(sixel.size((4, 2), &place), (10, 5));
+        assert_eq!(sixel.size((50, 1000), &Placement::default()), (5, 100));
+        assert_eq!(sixel.size((4, 2), &Placement::default()), (4, 2));
+    }
+}
@@ -1,18 +1,72 @@
 use image::GenericImageView;
 
 use crate::prelude::*;
+use crate::render::gpu::image::{Alpha, ImageInfo, ImageView, PixelFormat};
 
 pub struct RawImage {
-    pub color: image::ColorType,
-    pub size: (u32, u32),
-    pub data: Box<[u8]>,
+    pub info: ImageInfo,
+    pub data: ImageData,
+}
+
+/// The bytes behind a [`RawImage`].
+pub enum ImageData {
+    Owned(Box<[u8]>),
+    /// Memory shared with a client, shown without copying it first.
+    Mapped(memmap2::Mmap),
+}
+
+impl std::ops::Deref for ImageData {
+    type Target = [u8];
+
+    #[inline]
+    fn deref(&self) -> &[u8] {
+        match self {
+            ImageData::Owned(data) => data,
+            ImageData::Mapped(map) => map,
+        }
+    }
+}
+
+impl From<Box<[u8]>> for ImageData {
+    #[inline]
+    fn from(value: Box<[u8]>) -> Self {
+        Self::Owned(value)
+    }
+}
+
+impl From<memmap2::Mmap> for ImageData {
+    #[inline]
+    fn from(value: memmap2::Mmap) -> Self {
+        Self::Mapped(value)
+    }
+}
+
+impl RawImage {
+    /// Wraps pixel data laid out as described by `info`, failing if the image
+    /// is empty or there is not enough data for it.
+    pub fn new(info: ImageInfo, data: impl Into<ImageData>) -> Option<Self> {
+        let data = data.into();
+        let fits = !info.is_empty() && data.len() >= info.byte_len();
+        fits.then_some(RawImage { info, data })
+    }
+
+    /// Wraps tightly packed pixel data, failing if there is not enough of it
+    /// for an image of the given size.
+    pub fn from_raw(format: PixelFormat, width: u32, height: u32, data: Box<[u8]>) -> Option<Self> {
+        Self::new(ImageInfo::new(format, width, height)?, data)
+    }
+
+    /// Borrows the image for uploading.
+    #[inline]
+    pub fn view(&self) -> ImageView<'_> {
+        ImageView::new(self.info, &self.data)
+    }
 }
 
 impl fmt::Debug for RawImage {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         f.debug_struct("RawImage")
-            .field("color", &self.color)
-            .field("size", &self.size)
+            .field("info", &self.info)
             .finish_non_exhaustive()
     }
 }
@@ -23,11 +77,42 @@ impl fmt::Debug for RawImage {
 //     }
 // }
 
-impl From<image::DynamicImage> for RawImage {
-    fn from(value: image::DynamicImage) -> Self {
-        let color = value.color();
-        let size = value.dimensions();
-        let data = value.into_rgb8().into_vec().into_boxed_slice();
-        RawImage { color, size, data }
+impl TryFrom<image::DynamicImage> for RawImage {
+    type Error = String;
+
+    /// Converts a decoded image to 8-bit RGB, or RGBA if it has alpha,
+    /// failing if it is empty or too wide to show.
+    fn try_from(value: image::DynamicImage) -> std::result::Result<Self, String> {
+        let (w, h) = value.dimensions();
+        let format = match value.color().has_alpha() {
+            true => PixelFormat::Rgba8(Alpha::Unpremultiplied),
+            false => PixelFormat::Rgb8,
+        };
+        let info =
+            ImageInfo::new(format, w, h).ok_or_else(|| format!("a {w}x{h} image is too wide"))?;
+        let data = match format {
+            PixelFormat::Rgb8 => value.into_rgb8().into_vec(),
+            _ => value.into_rgba8().into_vec(),
+        };
+        RawImage::new(info, data.into_boxed_slice())
+            .ok_or_else(|| format!("a {w}x{h} image is empty"))
+    }
+}
+
+/// Serde helpers for sending bytes as base64 strings in json.
+///
+/// Use with `#[serde(with = "crate::util::base64")]`.
+pub mod base64 {
+    use ::base64::engine::general_purpose::STANDARD;
+    use ::base64::Engine;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&STANDARD.encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+        let s = String::deserialize(d)?;
+        STANDARD.decode(s).map_err(serde::de::Error::custom)
     }
 }
@@ -1,11 +1,16 @@
 use crate::{prelude::*, ImvrEventLoopHandle};
 
-use crate::render::gpu::image::{GpuImage, ImageInfo, ImageView};
+use crate::logic::msg::{Fit, WindowProps};
+use crate::playlist::Playlist;
+use crate::render::gpu::image::{GpuImage, PixelFormat};
 use crate::render::gpu::{GpuContext, UniformsBuffer};
 use crate::render::uniforms::WindowUniforms;
-use ext::glam::{Affine2, UVec2, Vec2};
+use ext::glam::UVec2;
+use serde::Serialize;
+use std::path::PathBuf;
 use wgpu::{Color, Instance};
-use winit::window::WindowId;
+use winit::dpi::{PhysicalPosition, PhysicalSize};
+use winit::window::{Fullscreen, WindowId, WindowLevel};
 
 /// Window capable of displaying images using wgpu.
 #[derive(Debug)]
@@ -13,6 +18,9 @@ pub struct Window {
     /// The winit window.
     window: winit::window::Window,
 
+    /// The title the window was given, as not every platform can read it back.
+    title: String,
+
     /// If true, preserve the aspect ratio of images.
     pub preserve_aspect_ratio: bool,
 
@@ -31,10 +39,18 @@ pub struct Window {
     /// The image to display (if any).
     pub image: Option<GpuImage>,
 
-    /// Transformation to apply to the image, in virtual window space.
-    ///
-    /// Virtual window space goes from (0, 0) in the top left to (1, 1) in the bottom right.
-    pub user_transform: Affine2,
+    /// The file the image was loaded from, if it came from one.
+    pub image_path: Option<PathBuf>,
+
+    /// The images this window steps through.
+    pub playlist: Playlist,
+
+    /// How far the image is zoomed in, 1 being its size when it is placed
+    /// in the window.
+    pub zoom: f32,
+
+    /// How far the image is rotated clockwise, in degrees.
+    pub rotation: f32,
 
     /// The context to the gpu for this image
     pub context: GpuContext,
@@ -44,43 +60,67 @@ pub struct Window {
 
 impl Window {
     /// Create a new window.
+    ///
+    /// `title` is used when `props` does not give one.
     pub fn new(
         title: impl Into<String>,
+        props: WindowProps,
         event_loop: &ImvrEventLoopHandle,
         instance: &Instance,
     ) -> Result<Self, WindowError> {
-        let window = winit::window::WindowBuilder::new()
-            .with_title(title)
-            .with_visible(true)
-            .with_resizable(true)
-            // .with_decorations(true)
-            // .with_window_icon(Some(Icon::from_rgba(rgba, width, height)))
-            // .with_transparent(true)
-            // .with_enabled_buttons(WindowButtons::empty())
+        let title = props.title.unwrap_or_else(|| title.into());
+        let mut builder = winit::window::WindowBuilder::new()
+            .with_title(&title)
+            .with_visible(props.visible.unwrap_or(true))
+            .with_resizable(props.resizable.unwrap_or(true))
+            .with_decorations(props.decorations.unwrap_or(true));
+        // .with_window_icon(Some(Icon::from_rgba(rgba, width, height)))
+        // .with_transparent(true)
+        // .with_enabled_buttons(WindowButtons::empty())
+
+        if let Some(size) = props.size {
+            builder = builder.with_inner_size(PhysicalSize::from(size));
+        }
+        if let Some(position) = props.position {
+            builder = builder.with_position(PhysicalPosition::from(position));
+        }
+        if let Some(size) = props.min_size {
+            builder = builder.with_min_inner_size(PhysicalSize::from(size));
+        }
+        if let Some(size) = props.max_size {
+            builder = builder.with_max_inner_size(PhysicalSize::from(size));
+        }
+        if let Some(on_top) = props.always_on_top {
+            builder = builder.with_window_level(window_level(on_top));
+        }
+        if let Some(fullscreen) = props.fullscreen {
+            builder = builder.with_fullscreen(borderless(fullscreen));
+        }
+
+        let window = builder
             .build(event_loop)
-            .unwrap();
+            .change_context(WindowError)
+            .attach_printable("unable to build the window")?;
 
         // window.request_redraw();
         // window.pre_present_notify();
 
-        let surface = instance.create_surface(&window).unwrap();
+        let surface = instance
+            .create_surface(&window)
+            .change_context(WindowError)
+            .attach_printable("unable to make a surface for the window")?;
         let surface = unsafe { std::mem::transmute(surface) };
 
-        let gpu = GpuContext::new(instance, wgpu::TextureFormat::Bgra8Unorm, &surface).unwrap();
+        let gpu = GpuContext::new(instance, wgpu::TextureFormat::Bgra8Unorm, &surface)
+            .change_context(WindowError)?;
 
         let a = futures::executor::block_on(
             instance.request_adapter(&wgpu::RequestAdapterOptions::default()),
-        );
-        let a = a.unwrap();
+        )
+        .ok_or_else(|| Report::new(WindowError).attach_printable("no gpu adapter found"))?;
 
         let winit::dpi::PhysicalSize { width, height } = window.inner_size();
-        // let size = UVec2::new(width, height);
-
-        let mut config = surface
-            .get_default_config(&a, width, height)
-            .unwrap();
-        config.format = wgpu::TextureFormat::Bgra8Unorm;
-        surface.configure(&gpu.device, &config);
+        configure(&surface, &a, &gpu.device, UVec2::new(width, height))?;
 
         let uniforms = UniformsBuffer::from_value(
             &gpu.device,
@@ -90,12 +130,16 @@ impl Window {
 
         Ok(Window {
             window,
-            preserve_aspect_ratio: true,
-            background_color: wgpu::Color::default(),
+            title,
+            preserve_aspect_ratio: props.fit.unwrap_or_default() == Fit::Contain,
+            background_color: props.background.map(color).unwrap_or_default(),
             surface,
             uniforms,
             image: None,
-            user_transform: Affine2::IDENTITY,
+            image_path: None,
+            playlist: Playlist::default(),
+            zoom: 1.0,
+            rotation: 0.0,
             context: gpu,
             adapter: a,
         })
@@ -111,34 +155,33 @@ impl Window {
     pub fn calculate_uniforms(&self) -> WindowUniforms {
         if let Some(image) = &self.image {
             let image_size = image.info().size.as_vec2();
-            if !self.preserve_aspect_ratio {
-                WindowUniforms::new_stretched(image_size) // .pre_apply_transform(self.user_transform)
-            } else {
-                let window_size = UVec2::new(
-                    self.window.inner_size().width,
-                    self.window.inner_size().height,
-                )
-                .as_vec2();
-                WindowUniforms::fit(window_size, image_size)
-                // .pre_apply_transform(self.user_transform)
-            }
+            let window_size = UVec2::new(
+                self.window.inner_size().width,
+                self.window.inner_size().height,
+            )
+            .as_vec2();
+            WindowUniforms::place(
+                window_size,
+                image_size,
+                self.preserve_aspect_ratio,
+                self.zoom,
+                self.rotation.to_radians(),
+            )
         } else {
-            WindowUniforms::new(self.user_transform, Vec2::ZERO)
+            WindowUniforms::new_empty()
         }
     }
 
     /// Resize a window.
-    pub fn resize(&mut self, size: UVec2) {
+    pub fn resize(&mut self, size: UVec2) -> Result<(), WindowError> {
         log::trace!("resize: ({},{})", size.x, size.y);
         debug_assert!(size.x > 0 && size.y > 0);
 
         // Create a swap chain for a surface.
-
-        let mut config = self.surface.get_default_config(&self.adapter, size.x, size.y).unwrap();
-        config.format = wgpu::TextureFormat::Bgra8Unorm;
-        self.surface.configure(&self.context.device, &config);
+        configure(&self.surface, &self.adapter, &self.context.device, size)?;
 
         self.uniforms.mark_dirty(true);
+        Ok(())
     }
 
     /// Render the contents of a window.
@@ -206,27 +249,178 @@ impl Window {
         Ok(())
     }
 
-    pub fn set_image(&mut self, image: crate::util::RawImage) {
-        let (w, h) = image.size;
+    /// Applies changes a client asked for.
+    pub fn set_props(&mut self, props: WindowProps) {
+        let window = &self.window;
+
+        if let Some(title) = props.title {
+            window.set_title(&title);
+            self.title = title;
+        }
+        if let Some(size) = props.size {
+            // the resize event that follows updates the surface
+            let _ = window.request_inner_size(PhysicalSize::from(size));
+        }
+        if let Some(position) = props.position {
+            window.set_outer_position(PhysicalPosition::from(position));
+        }
+        if let Some(size) = props.min_size {
+            window.set_min_inner_size(Some(PhysicalSize::from(size)));
+        }
+        if let Some(size) = props.max_size {
+            window.set_max_inner_size(Some(PhysicalSize::from(size)));
+        }
+        if let Some(decorations) = props.decorations {
+            window.set_decorations(decorations);
+        }
+        if let Some(on_top) = props.always_on_top {
+            window.set_window_level(window_level(on_top));
+        }
+        if let Some(fullscreen) = props.fullscreen {
+            window.set_fullscreen(borderless(fullscreen));
+        }
+        if let Some(visible) = props.visible {
+            window.set_visible(visible);
+        }
+        if let Some(resizable) = props.resizable {
+            window.set_resizable(resizable);
+        }
+        if let Some(background) = props.background {
+            self.background_color = color(background);
+            self.window.request_redraw();
+        }
+        if let Some(fit) = props.fit {
+            self.preserve_aspect_ratio = fit == Fit::Contain;
+            self.uniforms.mark_dirty(true);
+            self.window.request_redraw();
+        }
+    }
+
+    /// Describes the window for clients that ask about it.
+    pub fn info(&self, id: u64, name: Option<String>) -> WindowInfo {
+        let size = self.window.inner_size();
+        let Color { r, g, b, a } = self.background_color;
+        WindowInfo {
+            id,
+            name,
+            title: self.title.clone(),
+            width: size.width,
+            height: size.height,
+            image: self.image.as_ref().map(|image| {
+                let info = image.info();
+                ImageSummary {
+                    path: self.image_path.clone(),
+                    width: info.size.x,
+                    height: info.size.y,
+                    format: info.pixel_format,
+                }
+            }),
+            playlist: (!self.playlist.is_empty()).then(|| PlaylistSummary {
+                index: self.playlist.index(),
+                len: self.playlist.len(),
+            }),
+            fit: match self.preserve_aspect_ratio {
+                true => Fit::Contain,
+                false => Fit::Stretch,
+            },
+            zoom: self.zoom,
+            rotation: self.rotation,
+            background: [r, g, b, a],
+        }
+    }
+
+    /// Zooms to `factor`, or by it if `relative`.
+    pub fn zoom(&mut self, factor: f32, relative: bool) {
+        self.zoom = match relative {
+            true => self.zoom * factor,
+            false => factor,
+        };
+        self.uniforms.mark_dirty(true);
+        self.window.request_redraw();
+    }
+
+    /// Rotates clockwise by `degrees` on top of the rotation so far.
+    pub fn rotate(&mut self, degrees: f32) {
+        self.rotation = (self.rotation + degrees).rem_euclid(360.0);
+        self.uniforms.mark_dirty(true);
+        self.window.request_redraw();
+    }
 
-        log::info!("Image color type is: {:?}", &image.color);
+    pub fn set_image(&mut self, image: crate::util::RawImage, path: Option<PathBuf>) {
+        log::debug!("Image pixel format is: {:?}", &image.info.pixel_format);
 
-        let image = ImageView::new(ImageInfo::new(image.color.into(), w, h), &image.data);
+        let image = image.view();
 
         let gpu = &self.context;
-        let gpu_im = GpuImage::from_data(
-            "imvr_gpu_image".into(),
-            &gpu.device,
-            &gpu.image_bind_group_layout,
-            &image,
-        );
+        // frames with the same layout as the last one go into its buffers
+        let written = self
+            .image
+            .as_ref()
+            .is_some_and(|old| old.write(&gpu.queue, &image));
+        if !written {
+            let gpu_im = GpuImage::from_data(
+                "imvr_gpu_image".into(),
+                &gpu.device,
+                &gpu.image_bind_group_layout,
+                &image,
+            );
+            self.image = Some(gpu_im);
+        }
 
-        self.image = Some(gpu_im);
+        self.image_path = path;
         self.uniforms.mark_dirty(true);
         self.window.request_redraw();
     }
 }
 
+#[inline]
+fn window_level(always_on_top: bool) -> WindowLevel {
+    match always_on_top {
+        true => WindowLevel::AlwaysOnTop,
+        false => WindowLevel::Normal,
+    }
+}
+
+#[inline]
+fn color([r, g, b, a]: [f64; 4]) -> Color {
+    Color { r, g, b, a }
+}
+
+#[inline]
+fn borderless(fullscreen: bool) -> Option<Fullscreen> {
+    fullscreen.then_some(Fullscreen::Borderless(None))
+}
+
+/// Sets `surface` up to be drawn to at `size`.
+///
+/// Fails rather than configuring a size the device can not draw to, since
+/// wgpu panics on that.
+fn configure(
+    surface: &wgpu::Surface,
+    adapter: &wgpu::Adapter,
+    device: &wgpu::Device,
+    size: UVec2,
+) -> Result<(), WindowError> {
+    let max = device.limits().max_texture_dimension_2d;
+    if size.x == 0 || size.y == 0 || size.x > max || size.y > max {
+        return Err(Report::new(WindowError)).attach_printable_lazy(|| {
+            format!(
+                "can not draw to {}x{} pixels, the most is {max}",
+                size.x, size.y
+            )
+        });
+    }
+
+    let mut config = surface
+        .get_default_config(adapter, size.x, size.y)
+        .ok_or_else(|| {
+            Report::new(WindowError).attach_printable("the surface does not suit the gpu adapter")
+        })?;
+    config.format = wgpu::TextureFormat::Bgra8Unorm;
+    surface.configure(device, &config);
+    Ok(())
+}
+
 /// Create a surface configurations from a size
 // const fn surface_config(size: UVec2) -> wgpu::SurfaceConfiguration {
 //     wgpu::SurfaceConfiguration {
@@ -241,6 +435,41 @@ impl Window {
 //     }
 // }
 
+/// The state of a window as sent to clients.
+#[derive(Debug, Serialize)]
+pub struct WindowInfo {
+    pub id: u64,
+    pub name: Option<String>,
+    pub title: String,
+    /// Inner size in physical pixels.
+    pub width: u32,
+    pub height: u32,
+    pub image: Option<ImageSummary>,
+    pub playlist: Option<PlaylistSummary>,
+    pub fit: Fit,
+    pub zoom: f32,
+    /// Clockwise, in degrees.
+    pub rotation: f32,
+    /// Red, green, blue and alpha from 0 to 1.
+    pub background: [f64; 4],
+}
+
+/// The image a window is showing.
+#[derive(Debug, Serialize)]
+pub struct ImageSummary {
+    pub path: Option<PathBuf>,
+    pub width: u32,
+    pub height: u32,
+    pub format: PixelFormat,
+}
+
+/// Where a window is in its playlist.
+#[derive(Debug, Serialize)]
+pub struct PlaylistSummary {
+    pub index: usize,
+    pub len: usize,
+}
+
 #[derive(Debug)]
 pub struct WindowError;
 impl fmt::Display for WindowError {